@@ -5,6 +5,7 @@
 use kkowa_proxy_lib::{auth::{Authenticator, Credentials, Error},
                       http::Uri};
 use server_openapi::apis::{configuration::Configuration, users_api::users_me_api_users_me_get};
+use sha2::{Digest, Sha256};
 use tracing::{debug, trace};
 
 #[derive(Debug)]
@@ -54,6 +55,23 @@ async fn authenticate(&self, credentials: &Credentials) -> Result<(), Error> {
     }
 }
 
+/// Identify user of credentials; username for basic scheme, credentials itself (such as token) for others.
+pub fn user_of(credentials: &Credentials) -> Option<String> {
+    if credentials.scheme().eq_ignore_ascii_case("basic") {
+        let decoded = base64::decode(credentials.credentials()).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+
+        return decoded.split_once(':').map(|(user, _)| user.to_string());
+    }
+
+    Some(credentials.credentials().to_string())
+}
+
+/// Identify user of credentials by SHA-256 digest of [`user_of`], safe to log or store as it reveals no secret.
+pub fn user_digest(credentials: &Credentials) -> Option<String> {
+    user_of(credentials).map(|user| hex::encode(Sha256::digest(user)))
+}
+
 #[cfg(test)]
 mod tests {
     use httpmock::prelude::*;
@@ -61,7 +79,7 @@ mod tests {
     use rstest::*;
     use server_openapi::models::User;
 
-    use super::Delegator;
+    use super::{user_digest, user_of, Delegator};
 
     #[fixture]
     fn server() -> MockServer {
@@ -119,4 +137,28 @@ async fn delegator_unauthenticated(server: MockServer) {
             Err(Error::NotAuthenticated)
         ))
     }
+
+    #[test]
+    fn user_of_credentials() {
+        assert_eq!(
+            user_of(&Credentials::new("Basic", "dXNlcm5hbWU6cGFzc3dvcmQ=")), // username:password
+            Some("username".to_string())
+        );
+        assert_eq!(
+            user_of(&Credentials::new("Bearer", "TOKEN")),
+            Some("TOKEN".to_string())
+        );
+    }
+
+    #[test]
+    fn user_digest_credentials() {
+        let digest = user_digest(&Credentials::new("Bearer", "TOKEN")).unwrap();
+
+        assert_eq!(digest.len(), 64);
+        assert!(!digest.contains("TOKEN"));
+        assert_eq!(
+            user_digest(&Credentials::new("Bearer", "TOKEN")),
+            Some(digest)
+        );
+    }
 }
@@ -0,0 +1,512 @@
+//! Compressed on-disk archive of raw flows, for reprocessing with fixed processors later.
+//!
+//! Flows are stored as gzip-compressed NDJSON segment files, one per hour. Each record is written as its own gzip
+//! member, so segments stay readable even if process stops in the middle of write.
+
+use std::{fs::{self, File, OpenOptions},
+          io::{BufRead, BufReader, Write},
+          path::{Path, PathBuf},
+          sync::{Arc, Mutex},
+          time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use kkowa_proxy_lib::{auth::Credentials,
+                      http::{Response, Uri},
+                      proxy::{Flow, Handler, Reverse}};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, warn};
+
+use super::{host::{absolutize, Target},
+            is_empty, redact, upload, Collector, Processors};
+use crate::{auth::user_digest,
+            har::{Entry, DEFAULT_REDACT}};
+
+const SEGMENT_PREFIX: &str = "flows-";
+const SEGMENT_SUFFIX: &str = ".ndjson.gz";
+const SEGMENT_FORMAT: &str = "%Y%m%d%H";
+
+/// Number of writes between retention checks, besides on every new segment.
+const RETENTION_INTERVAL: usize = 100;
+
+/// Archived flow.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Record {
+    /// Digest of user made the flow, to upload reprocessed documents only on behalf of same user. Credentials are
+    /// never archived.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+
+    /// Flow, with credential headers redacted.
+    pub entry: Entry,
+}
+
+/// Archive directory with size and age based retention.
+#[derive(Debug)]
+pub struct Archive {
+    dir: PathBuf,
+
+    /// Maximum total size of segments in bytes.
+    max_bytes: u64,
+
+    /// Maximum age of segments.
+    max_age: Duration,
+
+    /// Hour of segment last written to.
+    current: Option<String>,
+
+    writes: usize,
+}
+
+impl Archive {
+    /// Create new archive at given directory.
+    pub fn new(dir: PathBuf, max_bytes: u64, max_age: Duration) -> Self {
+        Self {
+            dir,
+            max_bytes,
+            max_age,
+            current: None,
+            writes: 0,
+        }
+    }
+
+    /// Append record to segment of given time.
+    pub fn write(&mut self, record: &Record, at: DateTime<Utc>) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        let hour = at.format(SEGMENT_FORMAT).to_string();
+        let path = self.dir.join(segment_name(&hour));
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        serde_json::to_writer(&mut encoder, record)?;
+        encoder.write_all(b"\n")?;
+        encoder.finish()?;
+
+        self.writes += 1;
+        if self.current.as_ref() != Some(&hour) || self.writes % RETENTION_INTERVAL == 0 {
+            self.current = Some(hour);
+            self.enforce_retention(at)?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove segments older than maximum age, then oldest ones until total size fits in limit.
+    pub fn enforce_retention(&self, now: DateTime<Utc>) -> Result<()> {
+        let max_age = chrono::Duration::from_std(self.max_age)?;
+        let mut segments = Vec::new();
+        for (start, path) in segments_in(&self.dir)? {
+            // Segment may contain flows until an hour after its start
+            if start + chrono::Duration::hours(1) < now - max_age {
+                debug!("removing expired archive segment {path:?}");
+                fs::remove_file(&path)?;
+                continue;
+            }
+
+            let size = fs::metadata(&path)?.len();
+            segments.push((path, size));
+        }
+
+        let mut total: u64 = segments.iter().map(|(_, size)| size).sum();
+        for (path, size) in segments {
+            // Keep the latest segment being written
+            let current = self.current.as_ref().map_or(false, |hour| {
+                path.file_name() == Some(segment_name(hour).as_ref())
+            });
+            if total <= self.max_bytes || current {
+                break;
+            }
+
+            debug!("removing archive segment {path:?} exceeding size limit");
+            fs::remove_file(&path)?;
+            total -= size;
+        }
+
+        Ok(())
+    }
+
+    /// Read records made within given time range, in time order. Lines failed to parse are given as errors in place,
+    /// so single corrupt record does not prevent reading others.
+    pub fn read(dir: &Path, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<Result<Record>>> {
+        let mut records = Vec::new();
+        for (start, path) in segments_in(dir)? {
+            if start + chrono::Duration::hours(1) <= from || start > to {
+                continue;
+            }
+
+            let reader = BufReader::new(MultiGzDecoder::new(File::open(&path)?));
+            for line in reader.lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(err) => {
+                        // Possibly truncated by interrupted write
+                        warn!("stopped reading archive segment {path:?}: {err}");
+                        break;
+                    }
+                };
+
+                let record = serde_json::from_str::<Record>(&line)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|record| {
+                        let at = DateTime::parse_from_rfc3339(&record.entry.started_date_time)?
+                            .with_timezone(&Utc);
+                        Ok((at, record))
+                    });
+                match record {
+                    Ok((at, record)) if from <= at && at <= to => records.push(Ok(record)),
+                    Ok(_) => {}
+                    Err(err) => records.push(Err(
+                        err.context(format!("corrupt record in archive segment {path:?}"))
+                    )),
+                }
+            }
+        }
+
+        Ok(records)
+    }
+}
+
+/// File name of segment of given hour.
+fn segment_name(hour: &str) -> String {
+    format!("{SEGMENT_PREFIX}{hour}{SEGMENT_SUFFIX}")
+}
+
+/// List segments in directory with their starting time, oldest first.
+fn segments_in(dir: &Path) -> Result<Vec<(DateTime<Utc>, PathBuf)>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut segments = Vec::new();
+    for entry in dir.read_dir()? {
+        let path = entry?.path();
+        let start = {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            name.strip_prefix(SEGMENT_PREFIX)
+                .and_then(|s| s.strip_suffix(SEGMENT_SUFFIX))
+                .and_then(|hour| {
+                    NaiveDateTime::parse_from_str(&format!("{hour}0000"), "%Y%m%d%H%M%S").ok()
+                })
+        };
+
+        if let Some(start) = start {
+            segments.push((Utc.from_utc_datetime(&start), path));
+        }
+    }
+    segments.sort();
+
+    Ok(segments)
+}
+
+/// Handler archiving raw flows of hosts having processors.
+#[derive(Debug)]
+pub struct Archiver {
+    processors: Processors,
+    archive: Arc<Mutex<Archive>>,
+
+    /// Names of headers to redact.
+    redact: Vec<String>,
+}
+
+impl Archiver {
+    /// Create new handler, redacting credential headers by default.
+    pub fn new(archive: Archive, processors: Processors) -> Self {
+        Self {
+            processors,
+            archive: Arc::new(Mutex::new(archive)),
+            redact: DEFAULT_REDACT.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    /// Redact given headers instead of default ones.
+    pub fn redact(mut self, names: Vec<String>) -> Self {
+        self.redact = names;
+        self
+    }
+}
+
+#[async_trait]
+impl Handler for Archiver {
+    async fn on_response(&self, flow: &Flow, mut resp: Response) -> Reverse {
+        absolutize(&mut resp.request, flow.tunnel());
+        let target = Target::of(&resp.request);
+        if target.map_or(false, |target| {
+            self.processors
+                .load()
+                .iter()
+                .any(|processor| processor.matches_target(&target))
+        }) {
+            let now = Utc::now();
+            let mut entry = Entry::from_response(&resp, now);
+            entry.redact(&self.redact);
+            let record = Record {
+                user: flow.auth().and_then(user_digest),
+                entry,
+            };
+
+            let archive = self.archive.clone();
+            tokio::task::spawn_blocking(move || {
+                if let Err(err) = archive.lock().unwrap().write(&record, now) {
+                    error!("failed to archive flow: {err:#}");
+                }
+            });
+        }
+
+        Reverse::DoNothing
+    }
+}
+
+/// Summary of reprocessing.
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Number of records read.
+    pub records: usize,
+
+    /// Number of records skipped; corrupt, failed to convert as flow, made by other user than one uploading or
+    /// generating no documents.
+    pub skipped: usize,
+
+    /// Number of documents uploaded.
+    pub uploaded: usize,
+
+    /// Number of documents failed to upload.
+    pub failed: usize,
+}
+
+/// Re-run processors over archived flows within time range. If `upload_as` is set, documents of flows made by user of
+/// given credentials are uploaded with those; otherwise documents are only generated, for dry runs. Global redaction
+/// rules are applied as collector does.
+pub async fn reprocess(
+    dir: &Path,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    processors: Processors,
+    redact: Vec<redact::Rule>,
+    upload_as: Option<(Uri, Credentials)>,
+) -> Result<Summary> {
+    let collector =
+        Collector::new(upload_as.as_ref().map(|(u, _)| u.clone()), processors).redact(redact);
+    let user = upload_as
+        .as_ref()
+        .and_then(|(_, credentials)| user_digest(credentials));
+    let mut summary = Summary::default();
+    for record in Archive::read(dir, from, to)? {
+        summary.records += 1;
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                warn!("skipping archived flow: {err:#}");
+                summary.skipped += 1;
+                continue;
+            }
+        };
+        if upload_as.is_some() && (record.user.is_none() || record.user != user) {
+            summary.skipped += 1;
+            continue;
+        }
+        let resp = match record.entry.to_response() {
+            Ok(resp) => resp,
+            Err(err) => {
+                warn!("skipping archived flow: {err:#}");
+                summary.skipped += 1;
+                continue;
+            }
+        };
+
+        let (document, changes) =
+            collector.process(&resp, record.user.as_deref().unwrap_or_default());
+        if is_empty(&document) {
+            debug!("no documents reprocessed from archived flow");
+            summary.skipped += 1;
+            continue;
+        }
+        match &upload_as {
+            Some((u, credentials)) => match upload(u, credentials, vec![document]).await {
+                Ok(()) => {
+                    collector.changes.record(&changes);
+                    summary.uploaded += 1;
+                }
+                Err(err) => {
+                    warn!("failed to upload reprocessed document: {err:#}");
+                    summary.failed += 1;
+                }
+            },
+            None => debug!("reprocessed document: {document:?}"),
+        }
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::OpenOptions, io::Write, str::FromStr, time::Duration};
+
+    use chrono::{TimeZone, Utc};
+    use flate2::{write::GzEncoder, Compression};
+    use httpmock::prelude::*;
+    use kkowa_proxy_lib::{auth::Credentials, http::Uri};
+
+    use super::{reprocess, segments_in, Archive, Record};
+    use crate::{auth::user_digest, collector::Processor, har::Har};
+
+    /// Maximum age long enough to keep all segments.
+    const FOREVER: Duration = Duration::from_secs(60 * 60 * 24 * 365 * 100);
+
+    fn record(credentials: Option<Credentials>) -> Record {
+        let mut entry = Har::from_str(include_str!("donuts.har"))
+            .unwrap()
+            .log
+            .entries
+            .remove(0);
+        entry.started_date_time = "2023-01-08T12:30:00.000Z".to_string();
+
+        Record {
+            user: credentials.as_ref().and_then(user_digest),
+            entry,
+        }
+    }
+
+    #[test]
+    fn archive_write_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = Archive::new(dir.path().to_path_buf(), u64::MAX, FOREVER);
+        let at = Utc.with_ymd_and_hms(2023, 1, 8, 12, 30, 0).unwrap();
+        archive.write(&record(None), at).unwrap();
+        archive.write(&record(None), at).unwrap();
+
+        let records = Archive::read(
+            dir.path(),
+            Utc.with_ymd_and_hms(2023, 1, 8, 12, 0, 0).unwrap(),
+            Utc.with_ymd_and_hms(2023, 1, 8, 13, 0, 0).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(Result::is_ok));
+
+        let records = Archive::read(
+            dir.path(),
+            Utc.with_ymd_and_hms(2023, 1, 8, 12, 31, 0).unwrap(),
+            Utc.with_ymd_and_hms(2023, 1, 8, 13, 0, 0).unwrap(),
+        )
+        .unwrap();
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    fn archive_retention_age() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = Archive::new(
+            dir.path().to_path_buf(),
+            u64::MAX,
+            Duration::from_secs(60 * 60),
+        );
+        archive
+            .write(
+                &record(None),
+                Utc.with_ymd_and_hms(2023, 1, 8, 10, 0, 0).unwrap(),
+            )
+            .unwrap();
+        archive
+            .write(
+                &record(None),
+                Utc.with_ymd_and_hms(2023, 1, 8, 13, 0, 0).unwrap(),
+            )
+            .unwrap();
+
+        assert_eq!(segments_in(dir.path()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn archive_retention_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = Archive::new(dir.path().to_path_buf(), 1, FOREVER);
+        for hour in 10..13 {
+            archive
+                .write(
+                    &record(None),
+                    Utc.with_ymd_and_hms(2023, 1, 8, hour, 0, 0).unwrap(),
+                )
+                .unwrap();
+        }
+
+        // Only the segment being written is kept
+        assert_eq!(segments_in(dir.path()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn archive_retention_size_dir_name() {
+        // Directory named like a segment hour must not be mistaken as current segment
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().join("2023010812");
+        let mut archive = Archive::new(dir.clone(), 1, FOREVER);
+        for hour in 10..13 {
+            archive
+                .write(
+                    &record(None),
+                    Utc.with_ymd_and_hms(2023, 1, 8, hour, 0, 0).unwrap(),
+                )
+                .unwrap();
+        }
+
+        assert_eq!(segments_in(&dir).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn reprocess_archive() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/documents")
+                .header("Authorization", "Bearer TOKEN");
+            then.status(200).json_body(serde_json::json!([]));
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = Archive::new(dir.path().to_path_buf(), u64::MAX, FOREVER);
+        let at = Utc.with_ymd_and_hms(2023, 1, 8, 12, 30, 0).unwrap();
+        archive
+            .write(&record(Some(Credentials::new("Bearer", "TOKEN"))), at)
+            .unwrap();
+        archive
+            .write(&record(Some(Credentials::new("Bearer", "OTHER"))), at)
+            .unwrap();
+        archive.write(&record(None), at).unwrap();
+
+        // Matched by no processor
+        let mut unmatched = record(Some(Credentials::new("Bearer", "TOKEN")));
+        unmatched.entry.request.url = "http://unknown.domain.com/donuts".to_string();
+        archive.write(&unmatched, at).unwrap();
+
+        // Corrupt line
+        let (_, path) = segments_in(dir.path()).unwrap().remove(0);
+        let mut encoder = GzEncoder::new(
+            OpenOptions::new().append(true).open(path).unwrap(),
+            Compression::default(),
+        );
+        encoder.write_all(b"{\"user\":\n").unwrap();
+        encoder.finish().unwrap();
+
+        let summary = reprocess(
+            dir.path(),
+            Utc.with_ymd_and_hms(2023, 1, 8, 0, 0, 0).unwrap(),
+            Utc.with_ymd_and_hms(2023, 1, 9, 0, 0, 0).unwrap(),
+            vec![Processor::from_str(include_str!("donuts-processor.yaml")).unwrap()].into(),
+            vec![],
+            Some((
+                Uri::from_str(&server.url("")).unwrap(),
+                Credentials::new("Bearer", "TOKEN"),
+            )),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.records, 5);
+        assert_eq!(summary.uploaded, 1);
+        assert_eq!(summary.skipped, 4);
+        mock.assert();
+    }
+}
@@ -0,0 +1,308 @@
+//! Signed processor bundle module.
+//!
+//! Bundle is a TAR archive of processor definition YAML files and resources they refer to, such as output schemas, with
+//! `manifest.json`, which lists SHA-256 hashes of every file, and `manifest.sig`, base64-encoded ed25519 signature of
+//! manifest.
+
+use std::{collections::BTreeMap, io::Read, path::Path};
+
+use anyhow::{anyhow, bail, Context, Result};
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::Processor;
+
+const MANIFEST: &str = "manifest.json";
+const SIGNATURE: &str = "manifest.sig";
+
+/// Public keys trusted to sign bundles.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedKeys(Vec<PublicKey>);
+
+impl TrustedKeys {
+    /// Parse base64-encoded ed25519 public keys.
+    pub fn parse<S>(keys: &[S]) -> Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        let mut parsed = Vec::with_capacity(keys.len());
+        for key in keys {
+            let bytes = base64::decode(key.as_ref().trim()).context("public key is not base64")?;
+            parsed.push(PublicKey::from_bytes(&bytes).context("invalid ed25519 public key")?);
+        }
+
+        Ok(Self(parsed))
+    }
+
+    /// Whether no keys are trusted.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn verify(&self, message: &[u8], signature: &Signature) -> Result<()> {
+        if self.is_empty() {
+            bail!("no trusted keys configured to verify bundle");
+        }
+        if !self
+            .0
+            .iter()
+            .any(|key| key.verify_strict(message, signature).is_ok())
+        {
+            bail!("bundle signature does not match any of trusted keys");
+        }
+
+        Ok(())
+    }
+}
+
+impl From<Vec<PublicKey>> for TrustedKeys {
+    fn from(keys: Vec<PublicKey>) -> Self {
+        Self(keys)
+    }
+}
+
+/// Manifest of bundle files.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    /// SHA-256 hex digests of files keyed by file name.
+    files: BTreeMap<String, String>,
+}
+
+/// Unverified bundle read from archive.
+#[derive(Debug)]
+pub struct Bundle {
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl Bundle {
+    /// Read bundle archive.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut files = BTreeMap::new();
+        let mut archive = tar::Archive::new(bytes);
+        for entry in archive.entries().context("failed to read bundle archive")? {
+            let mut entry = entry.context("failed to read bundle entry")?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let name = entry.path()?.to_string_lossy().to_string();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            if files.insert(name.clone(), data).is_some() {
+                bail!("duplicate file {name} in bundle");
+            }
+        }
+
+        Ok(Self { files })
+    }
+
+    /// Verify bundle signature and file hashes, then load processors in it ordered by file name.
+    pub fn verify(mut self, keys: &TrustedKeys) -> Result<Vec<Processor>> {
+        let manifest = self
+            .files
+            .remove(MANIFEST)
+            .ok_or_else(|| anyhow!("bundle has no manifest"))?;
+        let signature = self
+            .files
+            .remove(SIGNATURE)
+            .ok_or_else(|| anyhow!("bundle is not signed"))?;
+        let signature = base64::decode(String::from_utf8_lossy(&signature).trim())
+            .context("bundle signature is not base64")?;
+        let signature =
+            Signature::try_from(signature.as_slice()).context("invalid bundle signature")?;
+        keys.verify(&manifest, &signature)?;
+
+        let manifest: Manifest =
+            serde_json::from_slice(&manifest).context("failed to parse bundle manifest")?;
+        for name in self.files.keys() {
+            if !manifest.files.contains_key(name) {
+                bail!("file {name} in bundle is not listed in manifest");
+            }
+        }
+
+        for (name, digest) in &manifest.files {
+            let data = self
+                .files
+                .get(name)
+                .ok_or_else(|| anyhow!("file {name} listed in manifest is missing"))?;
+            if &sha256(data) != digest {
+                bail!("hash of file {name} does not match to manifest");
+            }
+        }
+
+        // Files other than YAML are resources of processors, such as output schemas
+        let mut processors = Vec::with_capacity(manifest.files.len());
+        for name in manifest.files.keys() {
+            if !(name.ends_with(".yaml") || name.ends_with(".yml")) {
+                continue;
+            }
+
+            let mut processor = Processor::from_slice(&self.files[name])
+                .with_context(|| format!("failed to parse {name} in bundle as processor"))?;
+            processor
+                .resolve_schema(|schema| {
+                    let path = Path::new(name)
+                        .parent()
+                        .unwrap_or_else(|| Path::new(""))
+                        .join(schema);
+                    let path = path.to_string_lossy();
+                    self.files
+                        .get(path.as_ref())
+                        .cloned()
+                        .ok_or_else(|| anyhow!("output schema {path} is missing in bundle"))
+                })
+                .with_context(|| format!("failed to resolve output schema of {name} in bundle"))?;
+            processor
+                .validate()
+                .with_context(|| format!("processor {name} in bundle is invalid"))?;
+
+            processors.push(processor.with_source(name.as_str()));
+        }
+
+        Ok(processors)
+    }
+}
+
+/// Create signed bundle archive from processor definition files, given as pairs of file name and content.
+pub fn pack(files: &[(&str, &[u8])], keypair: &Keypair) -> Result<Vec<u8>> {
+    let mut manifest = Manifest::default();
+    for (name, data) in files {
+        manifest.files.insert(name.to_string(), sha256(data));
+    }
+    let manifest = serde_json::to_vec_pretty(&manifest)?;
+    let signature = base64::encode(keypair.sign(&manifest).to_bytes());
+
+    let mut builder = tar::Builder::new(Vec::new());
+    let entries = files.iter().copied().chain([
+        (MANIFEST, manifest.as_slice()),
+        (SIGNATURE, signature.as_bytes()),
+    ]);
+    for (name, data) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, name, data)?;
+    }
+
+    Ok(builder.into_inner()?)
+}
+
+fn sha256(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+    use rstest::*;
+
+    use super::{pack, Bundle, TrustedKeys};
+    use crate::collector::processor::tests::donuts;
+
+    /// Keypair generated from fixed seed for tests.
+    pub(crate) fn keypair(seed: u8) -> Keypair {
+        let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+
+        Keypair { secret, public }
+    }
+
+    #[fixture]
+    fn keys() -> TrustedKeys {
+        TrustedKeys::from(vec![keypair(1).public])
+    }
+
+    fn files() -> Vec<(&'static str, &'static [u8])> {
+        vec![
+            ("a.yaml", include_bytes!("donuts-processor.yaml")),
+            ("b.yaml", include_bytes!("donuts-processor.yaml")),
+        ]
+    }
+
+    #[test]
+    fn trusted_keys_parse() {
+        let key = base64::encode(keypair(1).public.as_bytes());
+
+        assert!(!TrustedKeys::parse(&[key]).unwrap().is_empty());
+        assert!(TrustedKeys::parse(&["invalid"]).is_err());
+    }
+
+    #[rstest]
+    fn bundle_verify(keys: TrustedKeys) {
+        let bytes = pack(&files(), &keypair(1)).unwrap();
+
+        assert_eq!(
+            Bundle::parse(&bytes).unwrap().verify(&keys).unwrap().len(),
+            2
+        );
+    }
+
+    #[rstest]
+    fn bundle_verify_untrusted(keys: TrustedKeys) {
+        let bytes = pack(&files(), &keypair(2)).unwrap();
+
+        assert!(Bundle::parse(&bytes).unwrap().verify(&keys).is_err());
+        assert!(Bundle::parse(&bytes)
+            .unwrap()
+            .verify(&TrustedKeys::default())
+            .is_err());
+    }
+
+    #[rstest]
+    fn bundle_verify_tampered(keys: TrustedKeys) {
+        let bytes = pack(&files(), &keypair(1)).unwrap();
+        let mut bundle = Bundle::parse(&bytes).unwrap();
+        bundle
+            .files
+            .insert("a.yaml".to_string(), b"tampered".to_vec());
+
+        assert!(bundle.verify(&keys).is_err());
+    }
+
+    #[rstest]
+    fn bundle_verify_unlisted(keys: TrustedKeys) {
+        let bytes = pack(&files(), &keypair(1)).unwrap();
+        let mut bundle = Bundle::parse(&bytes).unwrap();
+        bundle.files.insert(
+            "c.yaml".to_string(),
+            include_bytes!("donuts-processor.yaml").to_vec(),
+        );
+
+        assert!(bundle.verify(&keys).is_err());
+    }
+
+    #[rstest]
+    fn bundle_verify_unsigned(keys: TrustedKeys) {
+        let bytes = pack(&files(), &keypair(1)).unwrap();
+        let mut bundle = Bundle::parse(&bytes).unwrap();
+        bundle.files.remove("manifest.sig");
+
+        assert!(bundle.verify(&keys).is_err());
+    }
+
+    #[rstest]
+    #[case(
+        "apiVersion: collector.kkowa.io/v1\nkind: Processor\nmetadata:\n",
+        true
+    )]
+    #[case(
+        "apiVersion: collector.kkowa.io/v0\nkind: Processor\nmetadata:\n",
+        false
+    )]
+    #[case("apiVersion: collector.kkowa.io/v1\nkind: Other\nmetadata:\n", false)]
+    #[case(
+        "apiVersion: collector.kkowa.io/v1\nkind: Processor\nunknown: true\nmetadata:\n",
+        false
+    )]
+    fn bundle_verify_version(keys: TrustedKeys, #[case] header: &str, #[case] expected: bool) {
+        let source = donuts(&[("metadata:\n", header)]);
+        let bytes = pack(&[("a.yaml", source.as_bytes())], &keypair(1)).unwrap();
+
+        assert_eq!(
+            Bundle::parse(&bytes).unwrap().verify(&keys).is_ok(),
+            expected
+        );
+    }
+}
@@ -0,0 +1,285 @@
+//! Change detection of documents generated by rules with `emit: on-change`.
+
+use std::{collections::{BTreeMap, HashMap},
+          fs,
+          path::PathBuf,
+          sync::{Arc, Mutex}};
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+type JsonValue = serde_json::Value;
+
+/// Bounded store of content hashes of last delivered documents per key, evicting least recently updated keys first.
+#[derive(Clone, Debug)]
+pub struct ChangeStore {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Hash and update sequence number per key.
+    hashes: HashMap<String, (String, u64)>,
+
+    /// Keys by update sequence number, oldest first.
+    order: BTreeMap<u64, String>,
+
+    /// Sequence number of next update.
+    seq: u64,
+
+    capacity: usize,
+
+    /// File path to persist store to.
+    path: Option<PathBuf>,
+
+    /// Whether store changed since last save.
+    dirty: bool,
+}
+
+/// Hash of new or changed document for its key, to record once document delivered.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    key: String,
+    hash: String,
+}
+
+impl Default for ChangeStore {
+    fn default() -> Self {
+        Self::new(10_000)
+    }
+}
+
+impl ChangeStore {
+    /// Create new in-memory store keeping at most `capacity` keys.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                capacity: capacity.max(1),
+                ..Inner::default()
+            })),
+        }
+    }
+
+    /// Persist store to given file, loading previous contents from it if exists.
+    pub fn persist(self, path: PathBuf) -> Result<Self> {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if path.exists() {
+                let entries: Vec<(String, String)> = serde_json::from_slice(&fs::read(&path)?)
+                    .with_context(|| format!("failed to parse change store {path:?}"))?;
+                for (key, hash) in entries {
+                    inner.insert(key, hash);
+                }
+                debug!(
+                    "loaded {} change store entries from {path:?}",
+                    inner.order.len()
+                );
+            }
+            inner.path = Some(path);
+        }
+
+        Ok(self)
+    }
+
+    /// Write store to its file if persisted and changed since last save.
+    pub fn save(&self) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let path = match &inner.path {
+            Some(path) if inner.dirty => path.clone(),
+            _ => return Ok(()),
+        };
+
+        let entries: Vec<_> = inner
+            .order
+            .values()
+            .map(|key| (key, &inner.hashes[key].0))
+            .collect();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec(&entries)?)?;
+        fs::rename(&tmp, &path)?;
+        inner.dirty = false;
+
+        Ok(())
+    }
+
+    /// Compare document of rule for given user and key to last recorded one. Returns change to record once document
+    /// delivered if it is new or changed, or `None` if unchanged.
+    pub fn check(
+        &self,
+        user: &str,
+        processor: &str,
+        rule: &str,
+        key: &JsonValue,
+        document: &JsonValue,
+    ) -> Option<Change> {
+        let key = format!("{user}\0{processor}\0{rule}\0{key}");
+        let hash = hex::encode(Sha256::digest(document.to_string()));
+
+        let inner = self.inner.lock().unwrap();
+        match inner.hashes.get(&key) {
+            Some((last, _)) if last == &hash => None,
+            _ => Some(Change { key, hash }),
+        }
+    }
+
+    /// Record changes of delivered documents, so same documents are left out next time.
+    pub fn record(&self, changes: &[Change]) {
+        if changes.is_empty() {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        for change in changes {
+            inner.insert(change.key.clone(), change.hash.clone());
+        }
+        inner.dirty = true;
+    }
+
+    /// Number of keys stored.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().order.len()
+    }
+
+    /// Whether store is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Inner {
+    fn insert(&mut self, key: String, hash: String) {
+        let seq = self.seq;
+        self.seq += 1;
+        if let Some((_, last)) = self.hashes.insert(key.clone(), (hash, seq)) {
+            self.order.remove(&last);
+        }
+        self.order.insert(seq, key);
+        while self.order.len() > self.capacity {
+            if let Some((_, oldest)) = self.order.pop_first() {
+                self.hashes.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::ChangeStore;
+
+    type JsonValue = serde_json::Value;
+
+    /// Check document of `Shop / Price` rule and record it as delivered, returning whether it was new or changed.
+    fn deliver(store: &ChangeStore, user: &str, key: JsonValue, document: JsonValue) -> bool {
+        match store.check(user, "Shop", "Price", &key, &document) {
+            Some(change) => {
+                store.record(&[change]);
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[test]
+    fn changed() {
+        let store = ChangeStore::new(10);
+        let key = json!(["p1"]);
+
+        assert!(deliver(
+            &store,
+            "user",
+            key.clone(),
+            json!({ "price": 100 })
+        ));
+        assert!(!deliver(
+            &store,
+            "user",
+            key.clone(),
+            json!({ "price": 100 })
+        ));
+        assert!(deliver(&store, "user", key, json!({ "price": 90 })));
+        assert!(deliver(
+            &store,
+            "user",
+            json!(["p2"]),
+            json!({ "price": 90 })
+        ));
+    }
+
+    #[test]
+    fn changed_not_recorded() {
+        let store = ChangeStore::new(10);
+        let (key, document) = (json!(["p1"]), json!({ "price": 100 }));
+
+        // Not delivered, so still new
+        assert!(store
+            .check("user", "Shop", "Price", &key, &document)
+            .is_some());
+        assert!(store
+            .check("user", "Shop", "Price", &key, &document)
+            .is_some());
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn changed_evict_oldest() {
+        let store = ChangeStore::new(2);
+        for key in ["a", "b", "c"] {
+            deliver(&store, "user", json!(key), json!({}));
+        }
+
+        assert_eq!(store.len(), 2);
+        assert!(deliver(&store, "user", json!("a"), json!({})));
+        assert!(!deliver(&store, "user", json!("c"), json!({})));
+    }
+
+    #[test]
+    fn changed_evict_least_recently_updated() {
+        let store = ChangeStore::new(2);
+        let changed = |key, price| deliver(&store, "user", json!(key), json!({ "price": price }));
+        changed("a", 100);
+        changed("b", 100);
+        changed("a", 90);
+        changed("c", 100);
+
+        assert!(!changed("a", 90));
+        assert!(changed("b", 100));
+    }
+
+    #[test]
+    fn changed_per_user() {
+        let store = ChangeStore::new(10);
+        let key = json!(["p1"]);
+
+        assert!(deliver(
+            &store,
+            "alice",
+            key.clone(),
+            json!({ "price": 100 })
+        ));
+        assert!(deliver(&store, "bob", key.clone(), json!({ "price": 100 })));
+        assert!(!deliver(&store, "alice", key, json!({ "price": 100 })));
+    }
+
+    #[test]
+    fn persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("changes.json");
+
+        let store = ChangeStore::new(10).persist(path.clone()).unwrap();
+        deliver(&store, "user", json!("a"), json!({ "price": 100 }));
+        store.save().unwrap();
+
+        let store = ChangeStore::new(10).persist(path).unwrap();
+        assert_eq!(store.len(), 1);
+        assert!(!deliver(
+            &store,
+            "user",
+            json!("a"),
+            json!({ "price": 100 })
+        ));
+    }
+}
@@ -0,0 +1,440 @@
+//! Composition of processor definitions from base processors and shared rule fragments.
+//!
+//! Processor may `extends` another processor in same directory by its name. Base is deep-merged with child, where
+//! values of child take precedence, rules of same name are merged and other rules of child are appended. Base
+//! processors marked `abstract: true` are only used for inheritance and never loaded by themselves.
+//!
+//! Library files define named rule fragments under `fragments`. Rule may `include` one or more of them, which are
+//! merged in order before fields of rule itself. Fragments may include other fragments.
+
+use std::{collections::HashMap,
+          path::{Path, PathBuf}};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_yaml::{Mapping, Value};
+use tracing::warn;
+
+use super::{loader, Processor};
+
+/// YAML processor definitions of directory, indexed for composition.
+#[derive(Debug, Default)]
+pub struct Definitions {
+    /// Parsed definition files, or errors of parsing those.
+    files: HashMap<PathBuf, Result<Value, String>>,
+
+    /// Paths of processor definitions by processor name.
+    names: HashMap<String, PathBuf>,
+
+    /// Rule fragments by name.
+    fragments: HashMap<String, Value>,
+}
+
+impl Definitions {
+    /// Read all YAML definitions in given directory, or directory of given file.
+    pub fn read(path: &Path) -> Result<Self> {
+        let dir = match path.is_dir() {
+            true => path,
+            false => path.parent().unwrap_or_else(|| Path::new(".")),
+        };
+        let dir = match dir.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => dir,
+        };
+
+        let mut definitions = Self::default();
+        for def in loader::discover(dir)? {
+            if def
+                .extension()
+                .map_or(false, |ext| ext == loader::BUNDLE_EXTENSION)
+            {
+                continue;
+            }
+
+            let value = std::fs::read_to_string(&def)
+                .map_err(|err| err.to_string())
+                .and_then(|s| serde_yaml::from_str::<Value>(&s).map_err(|err| err.to_string()));
+            if let Ok(value) = &value {
+                definitions.index(&def, value)?;
+            }
+            definitions.files.insert(key(&def), value);
+        }
+
+        Ok(definitions)
+    }
+
+    /// Index processor name or fragments of definition file.
+    fn index(&mut self, def: &Path, value: &Value) -> Result<()> {
+        if let Some(fragments) = value.get("fragments").and_then(Value::as_mapping) {
+            for (name, fragment) in fragments {
+                let name = name
+                    .as_str()
+                    .ok_or_else(|| anyhow!("fragment name in {def:?} must be string"))?;
+                if self
+                    .fragments
+                    .insert(name.to_string(), fragment.clone())
+                    .is_some()
+                {
+                    bail!("rule fragment `{name}` defined more than once");
+                }
+            }
+        } else if let Some(name) = value["metadata"]["name"].as_str() {
+            if let Some(other) = self.names.get(name) {
+                warn!("processor name `{name}` of {def:?} already used by {other:?}");
+            } else {
+                self.names.insert(name.to_string(), def.to_path_buf());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether definition is processor using `extends` or `include`.
+    pub fn is_composed(&self, def: &Path) -> bool {
+        match self.file(def) {
+            Ok(value) => value.get("extends").is_some() || includes(value),
+            Err(_) => false,
+        }
+    }
+
+    /// Resolve processor definition. Returns `None` for libraries and abstract processors.
+    pub fn resolve(&self, def: &Path) -> Result<Option<Value>> {
+        let value = self.file(def)?;
+        if value.get("fragments").is_some() || value["abstract"].as_bool() == Some(true) {
+            return Ok(None);
+        }
+
+        let name = value["metadata"]["name"].as_str().unwrap_or_default();
+        let mut resolved = self.inherit(value, &mut vec![name.to_string()])?;
+        if let Some(rules) = resolved
+            .get_mut("spec")
+            .and_then(|spec| spec.get_mut("rules"))
+            .and_then(Value::as_sequence_mut)
+        {
+            for rule in rules.iter_mut() {
+                *rule = self.expand(rule.clone(), &mut vec![])?;
+            }
+        }
+
+        Ok(Some(resolved))
+    }
+
+    /// Load processor of definition. Returns `None` for libraries and abstract processors.
+    pub fn load(&self, def: &Path) -> Result<Option<Processor>> {
+        if !self.is_composed(def) {
+            return match self.resolve(def)? {
+                Some(_) => Processor::from_file(def).map(Some),
+                None => Ok(None),
+            };
+        }
+
+        match self.resolve(def)? {
+            Some(value) => {
+                let base_dir = def.parent().unwrap_or_else(|| Path::new("."));
+                Processor::from_value(value, base_dir).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn file(&self, def: &Path) -> Result<&Value> {
+        match self.files.get(&key(def)) {
+            Some(Ok(value)) => Ok(value),
+            Some(Err(err)) => bail!("failed to parse {def:?}: {err}"),
+            None => bail!("processor def {def:?} not found"),
+        }
+    }
+
+    /// Merge definition over base processors it extends, recursively.
+    fn inherit(&self, value: &Value, chain: &mut Vec<String>) -> Result<Value> {
+        let mut value = value.clone();
+        if let Some(map) = value.as_mapping_mut() {
+            map.remove("abstract");
+        }
+
+        let base = match value.as_mapping_mut().and_then(|map| map.remove("extends")) {
+            Some(Value::String(base)) => base,
+            Some(_) => bail!("extends must be name of base processor"),
+            None => return Ok(value),
+        };
+        if chain.contains(&base) {
+            bail!("cyclic extends: {} -> {base}", chain.join(" -> "));
+        }
+
+        let path = self
+            .names
+            .get(&base)
+            .ok_or_else(|| anyhow!("base processor `{base}` not found"))?;
+        chain.push(base.clone());
+        let base_value = self
+            .inherit(self.file(path)?, chain)
+            .with_context(|| format!("failed to resolve base processor `{base}`"))?;
+        chain.pop();
+
+        Ok(extend(base_value, value))
+    }
+
+    /// Merge fragments included by rule, recursively, then rule itself over them.
+    fn expand(&self, rule: Value, chain: &mut Vec<String>) -> Result<Value> {
+        let mut rule = rule;
+        let names = match rule.as_mapping_mut().and_then(|map| map.remove("include")) {
+            Some(Value::String(name)) => vec![name],
+            Some(Value::Sequence(names)) => names
+                .into_iter()
+                .map(|name| match name {
+                    Value::String(name) => Ok(name),
+                    _ => Err(anyhow!("include must be fragment name or list of those")),
+                })
+                .collect::<Result<_>>()?,
+            Some(_) => bail!("include must be fragment name or list of those"),
+            None => return Ok(rule),
+        };
+
+        let mut merged = Value::Mapping(Mapping::new());
+        for name in names {
+            if chain.contains(&name) {
+                bail!("cyclic include: {} -> {name}", chain.join(" -> "));
+            }
+            let fragment = self
+                .fragments
+                .get(&name)
+                .ok_or_else(|| anyhow!("rule fragment `{name}` not found"))?;
+
+            chain.push(name);
+            let fragment = self.expand(fragment.clone(), chain)?;
+            chain.pop();
+
+            merged = overlay(merged, fragment);
+        }
+
+        Ok(overlay(merged, rule))
+    }
+}
+
+/// Key of definition file, so same file is found however its path is spelled, e.g. `./donuts.yaml` or `donuts.yaml`.
+fn key(def: &Path) -> PathBuf {
+    def.canonicalize().unwrap_or_else(|_| def.to_path_buf())
+}
+
+/// Whether any rule of definition includes fragments.
+fn includes(value: &Value) -> bool {
+    value["spec"]["rules"].as_sequence().map_or(false, |rules| {
+        rules.iter().any(|rule| rule.get("include").is_some())
+    })
+}
+
+/// Merge child processor over base. Rules of same name are merged and other rules of child are appended.
+fn extend(base: Value, child: Value) -> Value {
+    let mut base = base;
+    let mut child = child;
+
+    let child_rules = child
+        .get_mut("spec")
+        .and_then(Value::as_mapping_mut)
+        .and_then(|spec| spec.remove("rules"));
+    let base_rules = base
+        .get_mut("spec")
+        .and_then(Value::as_mapping_mut)
+        .and_then(|spec| spec.remove("rules"));
+
+    let mut rules = match base_rules {
+        Some(Value::Sequence(rules)) => rules,
+        _ => vec![],
+    };
+    if let Some(Value::Sequence(child_rules)) = child_rules {
+        for rule in child_rules {
+            let existing = rule.get("name").and_then(|name| {
+                rules
+                    .iter_mut()
+                    .find(|base_rule| base_rule.get("name") == Some(name))
+            });
+            match existing {
+                Some(existing) => *existing = overlay(existing.clone(), rule),
+                None => rules.push(rule),
+            }
+        }
+    }
+
+    let mut merged = overlay(base, child);
+    if let Some(spec) = merged.get_mut("spec").and_then(Value::as_mapping_mut) {
+        spec.insert("rules".into(), Value::Sequence(rules));
+    }
+
+    merged
+}
+
+/// Merge mappings of `over` into `base` recursively, replacing other values.
+fn overlay(base: Value, over: Value) -> Value {
+    match (base, over) {
+        (Value::Mapping(mut base), Value::Mapping(over)) => {
+            for (key, value) in over {
+                let merged = match base.remove(&key) {
+                    Some(existing) => overlay(existing, value),
+                    None => value,
+                };
+                base.insert(key, merged);
+            }
+
+            Value::Mapping(base)
+        }
+        (_, over) => over,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::Definitions;
+
+    const BASE: &str = r#"
+abstract: true
+metadata:
+  name: Shop
+  hostname: ^shop.example.com$
+spec:
+  rules:
+    - name: Items
+      method: GET
+      path: ^/items$
+      request:
+        selectors: []
+      response:
+        selectors:
+          - key: items
+            value: $[*].name
+"#;
+
+    const CHILD: &str = r#"
+extends: Shop
+metadata:
+  name: Shop EU
+  hostname: ^shop.example.eu$
+spec:
+  rules:
+    - name: Items
+      path: ^/en/items$
+    - name: Cart
+      include: [json-get, cart]
+"#;
+
+    const LIBRARY: &str = r#"
+fragments:
+  json-get:
+    method: GET
+    request:
+      selectors: []
+  cart:
+    path: ^/cart$
+    response:
+      selectors:
+        - key: cart
+          value: $.items
+"#;
+
+    fn write(dir: &Path, files: &[(&str, &str)]) {
+        for (name, content) in files {
+            std::fs::write(dir.join(name), content).unwrap();
+        }
+    }
+
+    #[test]
+    fn definitions_load() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            &[
+                ("base.yaml", BASE),
+                ("child.yaml", CHILD),
+                ("library.yaml", LIBRARY),
+            ],
+        );
+        let definitions = Definitions::read(dir.path()).unwrap();
+
+        assert!(definitions
+            .load(&dir.path().join("base.yaml"))
+            .unwrap()
+            .is_none());
+        assert!(definitions
+            .load(&dir.path().join("library.yaml"))
+            .unwrap()
+            .is_none());
+
+        let resolved = definitions
+            .resolve(&dir.path().join("child.yaml"))
+            .unwrap()
+            .unwrap();
+        let rules = resolved["spec"]["rules"].as_sequence().unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0]["path"].as_str(), Some("^/en/items$"));
+        assert_eq!(rules[0]["method"].as_str(), Some("GET"));
+        assert_eq!(rules[1]["path"].as_str(), Some("^/cart$"));
+        assert!(resolved.get("extends").is_none());
+        assert!(resolved.get("abstract").is_none());
+
+        let processor = definitions
+            .load(&dir.path().join("child.yaml"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(processor.name(), "Shop EU");
+        assert!(processor.validate().is_ok());
+    }
+
+    #[test]
+    fn definitions_cyclic() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            &[
+                ("a.yaml", "extends: B\nmetadata:\n  name: A\n"),
+                ("b.yaml", "extends: A\nmetadata:\n  name: B\n"),
+                (
+                    "library.yaml",
+                    "fragments:\n  x:\n    include: y\n  y:\n    include: x\n",
+                ),
+                (
+                    "c.yaml",
+                    "metadata:\n  name: C\nspec:\n  rules:\n    - include: x\n",
+                ),
+            ],
+        );
+        let definitions = Definitions::read(dir.path()).unwrap();
+
+        let err = definitions.resolve(&dir.path().join("a.yaml")).unwrap_err();
+        assert!(format!("{err:#}").contains("cyclic extends"));
+        let err = definitions.resolve(&dir.path().join("c.yaml")).unwrap_err();
+        assert!(format!("{err:#}").contains("cyclic include"));
+    }
+
+    #[test]
+    fn definitions_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            &[("a.yaml", "extends: Nothing\nmetadata:\n  name: A\n")],
+        );
+        let definitions = Definitions::read(dir.path()).unwrap();
+
+        assert!(definitions.resolve(&dir.path().join("a.yaml")).is_err());
+    }
+
+    #[test]
+    fn definitions_path_spelling() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            &[
+                ("base.yaml", BASE),
+                ("child.yaml", CHILD),
+                ("library.yaml", LIBRARY),
+            ],
+        );
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        let definitions = Definitions::read(&dir.path().join("sub/../child.yaml")).unwrap();
+
+        let processor = definitions
+            .load(&dir.path().join("child.yaml"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(processor.name(), "Shop EU");
+    }
+}
@@ -0,0 +1,141 @@
+//! Time-windowed deduplication of uploaded documents.
+
+use std::{collections::{HashMap, VecDeque},
+          sync::{Arc, Mutex},
+          time::{Duration, Instant}};
+
+use metrics::increment_counter;
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+type JsonValue = serde_json::Value;
+
+/// Key of document, as user, folder and content hash.
+type Key = (String, String, String);
+
+/// Filter suppressing documents identical to one uploaded by same user to same folder within TTL.
+///
+/// Suppressed documents are counted to Prometheus counter `documents_deduplicated_total`.
+#[derive(Clone, Debug)]
+pub struct Dedup {
+    ttl: Duration,
+    capacity: usize,
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    seen: HashMap<Key, Instant>,
+
+    /// Keys with time seen, oldest first.
+    order: VecDeque<(Key, Instant)>,
+}
+
+impl Dedup {
+    /// Create new filter remembering at most `capacity` documents for `ttl`.
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity: capacity.max(1),
+            inner: Arc::default(),
+        }
+    }
+
+    /// Check whether document is duplicate of one uploaded within TTL.
+    pub fn is_duplicate(&self, user: &str, folder: &str, document: &JsonValue) -> bool {
+        self.check(user, folder, document, Instant::now())
+    }
+
+    /// Remember document as uploaded, to suppress its duplicates within TTL.
+    pub fn remember(&self, user: &str, folder: &str, document: &JsonValue) {
+        self.insert(user, folder, document, Instant::now())
+    }
+
+    fn check(&self, user: &str, folder: &str, document: &JsonValue, now: Instant) -> bool {
+        let key = key_of(user, folder, document);
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.expire(now, self.ttl);
+
+        if inner.seen.contains_key(&key) {
+            debug!("suppressed duplicate document to folder {folder}");
+            increment_counter!("documents_deduplicated_total");
+            return true;
+        }
+
+        false
+    }
+
+    fn insert(&self, user: &str, folder: &str, document: &JsonValue, now: Instant) {
+        let key = key_of(user, folder, document);
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.expire(now, self.ttl);
+        if inner.seen.insert(key.clone(), now).is_none() {
+            inner.order.push_back((key, now));
+        }
+        while inner.order.len() > self.capacity {
+            if let Some((oldest, _)) = inner.order.pop_front() {
+                inner.seen.remove(&oldest);
+            }
+        }
+    }
+}
+
+fn key_of(user: &str, folder: &str, document: &JsonValue) -> Key {
+    let hash = hex::encode(Sha256::digest(document.to_string()));
+
+    (user.to_string(), folder.to_string(), hash)
+}
+
+impl Inner {
+    /// Forget documents seen before TTL.
+    fn expire(&mut self, now: Instant, ttl: Duration) {
+        while let Some((_, seen)) = self.order.front() {
+            if now.duration_since(*seen) < ttl {
+                break;
+            }
+            if let Some((key, _)) = self.order.pop_front() {
+                self.seen.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use serde_json::json;
+
+    use super::Dedup;
+
+    #[test]
+    fn dedup_window() {
+        let dedup = Dedup::new(Duration::from_secs(60), 100);
+        let now = Instant::now();
+        let document = json!([{ "price": 100 }]);
+
+        // Not remembered until uploaded
+        assert!(!dedup.check("user", "temp", &document, now));
+        assert!(!dedup.check("user", "temp", &document, now));
+        dedup.insert("user", "temp", &document, now);
+        assert!(dedup.check("user", "temp", &document, now + Duration::from_secs(30)));
+        assert!(!dedup.check("other", "temp", &document, now + Duration::from_secs(30)));
+        assert!(!dedup.check("user", "temp", &json!([]), now + Duration::from_secs(30)));
+
+        // Expired
+        assert!(!dedup.check("user", "temp", &document, now + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn dedup_capacity() {
+        let dedup = Dedup::new(Duration::from_secs(60), 1);
+        let now = Instant::now();
+
+        dedup.insert("user", "temp", &json!(1), now);
+        assert!(dedup.check("user", "temp", &json!(1), now));
+        dedup.insert("user", "temp", &json!(2), now);
+        assert!(!dedup.check("user", "temp", &json!(1), now));
+    }
+}
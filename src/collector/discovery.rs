@@ -0,0 +1,291 @@
+//! Discovery of API endpoints not covered by any processor, by sampling unmatched JSON traffic.
+
+use std::{collections::{BTreeMap, BTreeSet},
+          sync::{Arc, Mutex},
+          time::{Duration, Instant}};
+
+use chrono::{DateTime, Utc};
+use kkowa_proxy_lib::http::Response;
+use serde::Serialize;
+use serde_json::json;
+use tracing::{debug, trace};
+
+use super::host::Target;
+
+type JsonValue = serde_json::Value;
+
+/// Placeholder replacing variable path segments such as IDs.
+const PLACEHOLDER: &str = "{id}";
+
+/// Sample of unmatched endpoint, deduplicated by method, host and path template.
+#[derive(Clone, Debug, Serialize)]
+pub struct Sample {
+    pub method: String,
+    pub host: String,
+
+    /// Path with variable segments replaced.
+    pub path: String,
+
+    /// Response status codes seen.
+    pub statuses: BTreeSet<u16>,
+
+    /// Top-level JSON shape of first response sampled.
+    pub shape: JsonValue,
+
+    /// Number of responses seen.
+    pub count: usize,
+
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Shared store of unmatched endpoint samples.
+#[derive(Clone, Debug)]
+pub struct Discovery {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    samples: BTreeMap<(String, String, String), Sample>,
+
+    /// Maximum number of new samples per minute.
+    rate: usize,
+
+    /// Maximum number of samples kept.
+    capacity: usize,
+
+    window: Instant,
+    taken: usize,
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Self::new(60, 1000)
+    }
+}
+
+impl Discovery {
+    /// Create new store taking at most `rate` new samples per minute and keeping at most `capacity` samples.
+    pub fn new(rate: usize, capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                samples: BTreeMap::new(),
+                rate,
+                capacity,
+                window: Instant::now(),
+                taken: 0,
+            })),
+        }
+    }
+
+    /// Record response which matched no processor rule, if it is JSON.
+    pub fn observe(&self, resp: &Response) {
+        let is_json = resp
+            .headers
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .map_or(false, |v| v.contains("json"));
+        if !is_json {
+            return;
+        }
+
+        let req = &resp.request;
+        let key = (
+            req.method.to_string(),
+            Target::of(req)
+                .map(|target| target.host)
+                .unwrap_or_default(),
+            template(req.uri.path()),
+        );
+        let now = Utc::now();
+
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(sample) = inner.samples.get_mut(&key) {
+            sample.count += 1;
+            sample.statuses.insert(resp.status.as_u16());
+            sample.last_seen = now;
+            return;
+        }
+
+        if !inner.take() {
+            trace!("discovery rate limit exceeded, sample dropped");
+            return;
+        }
+
+        let shape = match serde_json::from_slice::<JsonValue>(&resp.payload) {
+            Ok(body) => shape(&body),
+            Err(_) => return,
+        };
+        debug!("discovered unmatched endpoint {} {}{}", key.0, key.1, key.2);
+
+        let sample = Sample {
+            method: key.0.clone(),
+            host: key.1.clone(),
+            path: key.2.clone(),
+            statuses: BTreeSet::from([resp.status.as_u16()]),
+            shape,
+            count: 1,
+            first_seen: now,
+            last_seen: now,
+        };
+        inner.samples.insert(key, sample);
+    }
+
+    /// Samples collected so far, ordered by method, host and path.
+    pub fn samples(&self) -> Vec<Sample> {
+        self.inner
+            .lock()
+            .unwrap()
+            .samples
+            .values()
+            .cloned()
+            .collect()
+    }
+}
+
+impl Inner {
+    /// Take slot for new sample if rate and capacity allow.
+    fn take(&mut self) -> bool {
+        if self.samples.len() >= self.capacity {
+            return false;
+        }
+        if self.window.elapsed() >= Duration::from_secs(60) {
+            self.window = Instant::now();
+            self.taken = 0;
+        }
+        if self.taken >= self.rate {
+            return false;
+        }
+
+        self.taken += 1;
+        true
+    }
+}
+
+/// Replace variable segments of path, such as numbers, UUIDs and hashes, with placeholder.
+pub fn template(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match is_variable(segment) {
+            true => PLACEHOLDER,
+            false => segment,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn is_variable(segment: &str) -> bool {
+    if segment.is_empty() {
+        return false;
+    }
+
+    let digits = segment.chars().filter(char::is_ascii_digit).count();
+    let hex = segment.chars().all(|c| c.is_ascii_hexdigit() || c == '-');
+
+    digits == segment.len() || (hex && segment.len() >= 16) || (digits > 0 && segment.len() >= 24)
+}
+
+/// Top-level shape of JSON value; type names of object fields, or shape of first item of array.
+fn shape(value: &JsonValue) -> JsonValue {
+    match value {
+        JsonValue::Object(map) => JsonValue::Object(
+            map.iter()
+                .map(|(key, value)| (key.clone(), json!(type_name(value))))
+                .collect(),
+        ),
+        JsonValue::Array(items) => match items.first() {
+            Some(JsonValue::Array(_)) | None => json!([]),
+            Some(item) => json!([shape(item)]),
+        },
+        scalar => json!(type_name(scalar)),
+    }
+}
+
+fn type_name(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "boolean",
+        JsonValue::Number(_) => "number",
+        JsonValue::String(_) => "string",
+        JsonValue::Array(_) => "array",
+        JsonValue::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kkowa_proxy_lib::http::{Headers, Method, Request, Response, StatusCode, Uri, Version};
+    use rstest::*;
+    use serde_json::json;
+
+    use super::{shape, template, Discovery};
+
+    fn response(uri: &'static str, content_type: &str) -> Response {
+        let req = Request::new(
+            Method::GET,
+            Uri::from_static(uri),
+            Version::HTTP_11,
+            Headers::new(),
+            vec![],
+        );
+        let mut headers = http::HeaderMap::new();
+        headers.insert("content-type", content_type.parse().unwrap());
+
+        Response::new(
+            StatusCode::OK,
+            Version::HTTP_11,
+            Headers::from(headers),
+            br#"{"id": 1, "items": [], "name": "Cake"}"#.to_vec(),
+            req,
+        )
+    }
+
+    #[rstest]
+    #[case("/users/1234/posts", "/users/{id}/posts")]
+    #[case("/items/550e8400-e29b-41d4-a716-446655440000", "/items/{id}")]
+    #[case("/v2/donuts", "/v2/donuts")]
+    #[case("/", "/")]
+    fn path_template(#[case] path: &str, #[case] expected: &str) {
+        assert_eq!(template(path), expected);
+    }
+
+    #[test]
+    fn json_shape() {
+        assert_eq!(
+            shape(&json!([{ "id": 1, "tags": ["a"] }])),
+            json!([{ "id": "number", "tags": "array" }])
+        );
+    }
+
+    #[test]
+    fn observe_dedup() {
+        let discovery = Discovery::default();
+        discovery.observe(&response(
+            "http://api.domain.com/users/1",
+            "application/json",
+        ));
+        discovery.observe(&response(
+            "http://api.domain.com/users/2",
+            "application/json",
+        ));
+        discovery.observe(&response("http://api.domain.com/page", "text/html"));
+
+        let samples = discovery.samples();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].path, "/users/{id}");
+        assert_eq!(samples[0].count, 2);
+        assert_eq!(
+            samples[0].shape,
+            json!({ "id": "number", "items": "array", "name": "string" })
+        );
+    }
+
+    #[test]
+    fn observe_rate_limited() {
+        let discovery = Discovery::new(1, 10);
+        discovery.observe(&response("http://api.domain.com/a", "application/json"));
+        discovery.observe(&response("http://api.domain.com/b", "application/json"));
+
+        assert_eq!(discovery.samples().len(), 1);
+    }
+}
@@ -0,0 +1,215 @@
+//! Selector hit-rate tracking, to detect schema changes of target sites.
+//!
+//! Each selector evaluation is counted to Prometheus counters `selector_matches_total`, `selector_empty_total` and
+//! `selector_errors_total`, labeled by processor, rule and selector key. Hit rates are also tracked per time window
+//! in memory, to report selectors whose hit rate dropped sharply compared to previous windows.
+
+use std::{collections::HashMap,
+          sync::Mutex,
+          time::{Duration, Instant}};
+
+use metrics::increment_counter;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+/// Hit rates of selectors of this process.
+pub static DRIFT: Lazy<Drift> = Lazy::new(|| Drift::new(Duration::from_secs(60 * 60)));
+
+/// Minimum number of evaluations in window for its hit rate to be considered.
+const MIN_SAMPLES: usize = 10;
+
+/// Minimum baseline hit rate for drops to be reported.
+const MIN_BASELINE: f64 = 0.5;
+
+/// Ratio to baseline hit rate below which drop is reported.
+const DROP_RATIO: f64 = 0.5;
+
+/// Outcome of selector evaluation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Selector selected some values.
+    Match,
+
+    /// Selector selected nothing.
+    Empty,
+
+    /// Selector failed, such as body is not JSON.
+    Error,
+}
+
+/// Record outcome of selector evaluation.
+pub(crate) fn record(processor: &str, rule: &str, selector: &str, outcome: Outcome) {
+    let labels = [
+        ("processor", processor.to_string()),
+        ("rule", rule.to_string()),
+        ("selector", selector.to_string()),
+    ];
+    match outcome {
+        Outcome::Match => increment_counter!("selector_matches_total", &labels),
+        Outcome::Empty => increment_counter!("selector_empty_total", &labels),
+        Outcome::Error => increment_counter!("selector_errors_total", &labels),
+    }
+
+    DRIFT.record(
+        (
+            processor.to_string(),
+            rule.to_string(),
+            selector.to_string(),
+        ),
+        outcome == Outcome::Match,
+        Instant::now(),
+    );
+}
+
+type SelectorKey = (String, String, String);
+
+/// Hit rates of selectors per time window.
+#[derive(Debug)]
+pub struct Drift {
+    window: Duration,
+    stats: Mutex<HashMap<SelectorKey, Stats>>,
+}
+
+#[derive(Debug)]
+struct Stats {
+    started: Instant,
+    hits: usize,
+    total: usize,
+
+    /// Moving average of hit rates of previous windows.
+    baseline: Option<f64>,
+}
+
+/// Selector whose hit rate dropped.
+#[derive(Debug, Serialize)]
+pub struct Dropped {
+    pub processor: String,
+    pub rule: String,
+    pub selector: String,
+
+    /// Hit rate of previous windows.
+    pub baseline: f64,
+
+    /// Hit rate of current window.
+    pub current: f64,
+
+    /// Number of evaluations in current window.
+    pub samples: usize,
+}
+
+impl Drift {
+    /// Create new tracker comparing hit rates per given window.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            stats: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn record(&self, key: SelectorKey, hit: bool, now: Instant) {
+        let mut stats = self.stats.lock().unwrap();
+        let stats = stats.entry(key).or_insert_with(|| Stats {
+            started: now,
+            hits: 0,
+            total: 0,
+            baseline: None,
+        });
+
+        if now.duration_since(stats.started) >= self.window {
+            if stats.total >= MIN_SAMPLES {
+                let rate = stats.hits as f64 / stats.total as f64;
+                stats.baseline = Some(match stats.baseline {
+                    Some(baseline) => (baseline + rate) / 2.0,
+                    None => rate,
+                });
+            }
+            stats.started = now;
+            stats.hits = 0;
+            stats.total = 0;
+        }
+
+        stats.total += 1;
+        if hit {
+            stats.hits += 1;
+        }
+    }
+
+    /// Selectors whose hit rate of current window dropped sharply compared to previous windows.
+    pub fn dropped(&self) -> Vec<Dropped> {
+        let stats = self.stats.lock().unwrap();
+        let mut dropped: Vec<_> = stats
+            .iter()
+            .filter_map(|((processor, rule, selector), stats)| {
+                let baseline = stats.baseline?;
+                let current = stats.hits as f64 / stats.total as f64;
+                let drop = baseline >= MIN_BASELINE
+                    && stats.total >= MIN_SAMPLES
+                    && current < baseline * DROP_RATIO;
+
+                drop.then(|| Dropped {
+                    processor: processor.clone(),
+                    rule: rule.clone(),
+                    selector: selector.clone(),
+                    baseline,
+                    current,
+                    samples: stats.total,
+                })
+            })
+            .collect();
+        dropped.sort_by(|a, b| {
+            (&a.processor, &a.rule, &a.selector).cmp(&(&b.processor, &b.rule, &b.selector))
+        });
+
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Drift;
+
+    fn key() -> (String, String, String) {
+        (
+            "Name".to_string(),
+            "Donuts".to_string(),
+            "extracted.donutNames".to_string(),
+        )
+    }
+
+    #[test]
+    fn drift_dropped() {
+        let drift = Drift::new(Duration::from_secs(60));
+        let start = Instant::now();
+        for _ in 0..20 {
+            drift.record(key(), true, start);
+        }
+        assert!(drift.dropped().is_empty());
+
+        let next = start + Duration::from_secs(60);
+        for _ in 0..20 {
+            drift.record(key(), false, next);
+        }
+
+        let dropped = drift.dropped();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].selector, "extracted.donutNames");
+        assert_eq!(dropped[0].baseline, 1.0);
+        assert_eq!(dropped[0].current, 0.0);
+    }
+
+    #[test]
+    fn drift_stable() {
+        let drift = Drift::new(Duration::from_secs(60));
+        let start = Instant::now();
+        for window in 0..3 {
+            let now = start + Duration::from_secs(60 * window);
+            for i in 0..20 {
+                drift.record(key(), i % 4 != 0, now);
+            }
+        }
+
+        assert!(drift.dropped().is_empty());
+    }
+}
@@ -0,0 +1,270 @@
+//! Resolution of request targets and hostname patterns of processors.
+
+use anyhow::{bail, Result};
+use http::{uri::Authority, Method};
+use kkowa_proxy_lib::http::{Request, Uri};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Scheme, host and port request is sent to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Target {
+    /// Scheme, if known.
+    pub scheme: Option<String>,
+
+    /// Host in lowercase, without port.
+    pub host: String,
+
+    /// Port given explicitly, or default one of scheme.
+    pub port: Option<u16>,
+}
+
+impl Target {
+    /// Resolve target of request from its URI, falling back to `Host` header for origin-form URIs. Authority of
+    /// `CONNECT` requests is their URI itself.
+    ///
+    /// Origin-form requests are taken to be intercepted inside `CONNECT` tunnels, so their scheme is taken to be
+    /// `https`. Handlers resolve them against tunnel of their flow with [`absolutize`] first; `Host` header is only
+    /// relied on for requests without one, such as archived or replayed ones.
+    pub fn of(req: &Request) -> Option<Self> {
+        let mut scheme = req.uri.scheme_str().map(str::to_ascii_lowercase);
+        let authority = match req.uri.authority() {
+            Some(authority) => authority.clone(),
+            None if req.method == Method::CONNECT => return None,
+            None => {
+                scheme.get_or_insert_with(|| "https".to_string());
+                req.headers
+                    .get("host")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse::<Authority>().ok())?
+            }
+        };
+
+        let host = authority
+            .host()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_ascii_lowercase();
+        if host.is_empty() {
+            return None;
+        }
+        let port = authority.port_u16().or_else(|| match scheme.as_deref() {
+            Some("http") => Some(80),
+            Some("https") => Some(443),
+            _ => None,
+        });
+
+        Some(Self { scheme, host, port })
+    }
+}
+
+/// Rewrite origin-form URI of request intercepted inside `CONNECT` tunnel to absolute form with authority of tunnel, so
+/// its target does not depend on `Host` header sent by client.
+pub fn absolutize(req: &mut Request, tunnel: Option<&Authority>) {
+    let tunnel = match tunnel {
+        Some(tunnel) if req.uri.authority().is_none() && req.method != Method::CONNECT => tunnel,
+        _ => return,
+    };
+
+    let path = req.uri.path_and_query().map_or("/", |p| p.as_str());
+    match Uri::builder()
+        .scheme("https")
+        .authority(tunnel.clone())
+        .path_and_query(path)
+        .build()
+    {
+        Ok(uri) => req.uri = uri,
+        Err(err) => warn!(
+            "can't resolve request to {uri} against tunnel {tunnel}: {err}",
+            uri = req.uri
+        ),
+    }
+}
+
+/// Hostname pattern, exact hostname or wildcard domain such as `*.example.com` matching any of its subdomains.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct HostPattern(String);
+
+impl TryFrom<String> for HostPattern {
+    type Error = anyhow::Error;
+
+    fn try_from(pattern: String) -> Result<Self> {
+        let name = pattern.strip_prefix("*.").unwrap_or(&pattern);
+        if name.is_empty() || name.contains(['*', '/', ':']) {
+            bail!("invalid hostname pattern `{pattern}`");
+        }
+
+        Ok(Self(pattern.to_ascii_lowercase()))
+    }
+}
+
+impl From<HostPattern> for String {
+    fn from(pattern: HostPattern) -> Self {
+        pattern.0
+    }
+}
+
+impl HostPattern {
+    /// Check host, in lowercase, matches pattern.
+    pub fn matches(&self, host: &str) -> bool {
+        match self.0.strip_prefix("*.") {
+            Some(domain) => host
+                .strip_suffix(domain)
+                .map_or(false, |sub| sub.len() > 1 && sub.ends_with('.')),
+            None => host == self.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderMap;
+    use kkowa_proxy_lib::http::{Headers, Method, Request, Uri, Version};
+    use rstest::*;
+
+    use super::{absolutize, HostPattern, Target};
+
+    #[rstest]
+    #[case(
+        "https://Shop.Example.com/items",
+        Some("https"),
+        "shop.example.com",
+        Some(443)
+    )]
+    #[case(
+        "http://shop.example.com:8080/items",
+        Some("http"),
+        "shop.example.com",
+        Some(8080)
+    )]
+    #[case("/items", Some("https"), "origin.example.com", Some(443))]
+    fn target_of(
+        #[case] uri: &'static str,
+        #[case] scheme: Option<&str>,
+        #[case] host: &str,
+        #[case] port: Option<u16>,
+    ) {
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "origin.example.com".parse().unwrap());
+        let req = Request::new(
+            Method::GET,
+            Uri::from_static(uri),
+            Version::HTTP_11,
+            Headers::from(headers),
+            vec![],
+        );
+
+        assert_eq!(
+            Target::of(&req),
+            Some(Target {
+                scheme: scheme.map(str::to_string),
+                host: host.to_string(),
+                port,
+            })
+        );
+    }
+
+    #[test]
+    fn target_of_host_port() {
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "origin.example.com:8443".parse().unwrap());
+        let req = Request::new(
+            Method::GET,
+            Uri::from_static("/items"),
+            Version::HTTP_11,
+            Headers::from(headers),
+            vec![],
+        );
+
+        let target = Target::of(&req).unwrap();
+        assert_eq!(target.scheme.as_deref(), Some("https"));
+        assert_eq!(target.port, Some(8443));
+    }
+
+    #[test]
+    fn target_of_connect() {
+        let req = Request::new(
+            Method::CONNECT,
+            Uri::from_static("shop.example.com:443"),
+            Version::HTTP_11,
+            Headers::new(),
+            vec![],
+        );
+
+        let target = Target::of(&req).unwrap();
+        assert_eq!(target.host, "shop.example.com");
+        assert_eq!(target.port, Some(443));
+    }
+
+    #[rstest]
+    #[case(
+        "/items?page=2",
+        Some("shop.example.com:8443"),
+        "https://shop.example.com:8443/items?page=2"
+    )]
+    #[case("/items", None, "/items")]
+    #[case(
+        "http://other.example.com/items",
+        Some("shop.example.com"),
+        "http://other.example.com/items"
+    )]
+    fn absolutize_tunnel(
+        #[case] uri: &'static str,
+        #[case] tunnel: Option<&str>,
+        #[case] expected: &str,
+    ) {
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "origin.example.com".parse().unwrap());
+        let mut req = Request::new(
+            Method::GET,
+            Uri::from_static(uri),
+            Version::HTTP_11,
+            Headers::from(headers),
+            vec![],
+        );
+        absolutize(&mut req, tunnel.map(|t| t.parse().unwrap()).as_ref());
+
+        assert_eq!(req.uri.to_string(), expected);
+    }
+
+    #[test]
+    fn target_of_tunnel_over_host_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "origin.example.com".parse().unwrap());
+        let mut req = Request::new(
+            Method::GET,
+            Uri::from_static("/items"),
+            Version::HTTP_11,
+            Headers::from(headers),
+            vec![],
+        );
+        absolutize(&mut req, Some(&"shop.example.com".parse().unwrap()));
+
+        let target = Target::of(&req).unwrap();
+        assert_eq!(target.host, "shop.example.com");
+        assert_eq!(target.port, Some(443));
+    }
+
+    #[rstest]
+    #[case("shop.example.com", "shop.example.com", true)]
+    #[case("shop.example.com", "example.com", false)]
+    #[case("*.example.com", "shop.example.com", true)]
+    #[case("*.example.com", "a.b.example.com", true)]
+    #[case("*.example.com", "example.com", false)]
+    #[case("*.example.com", "badexample.com", false)]
+    fn host_pattern(#[case] pattern: &str, #[case] host: &str, #[case] expected: bool) {
+        let pattern = HostPattern::try_from(pattern.to_string()).unwrap();
+
+        assert_eq!(pattern.matches(host), expected);
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("*.")]
+    #[case("shop.*.com")]
+    #[case("shop.example.com:443")]
+    fn host_pattern_invalid(#[case] pattern: &str) {
+        assert!(HostPattern::try_from(pattern.to_string()).is_err());
+    }
+}
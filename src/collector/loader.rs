@@ -0,0 +1,183 @@
+//! Processor definition loading module.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use tracing::debug;
+
+use super::{bundle::{Bundle, TrustedKeys},
+            compose::Definitions,
+            redact, Processor};
+
+/// File extension of signed processor bundles.
+pub const BUNDLE_EXTENSION: &str = "bundle";
+
+/// Find processor definition files at given path. If path is directory, all YAML and bundle files in it are returned
+/// in lexicographical order; otherwise path itself is returned.
+pub fn discover<P>(path: P) -> Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    if !path.exists() {
+        bail!("processor path {path:?} is neither directory or file, may not exists")
+    }
+
+    if !path.is_dir() {
+        debug!("specified processor path is single file: {path:?}");
+
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    debug!("looking for processor defs from directory {path:?}");
+    let mut defs = Vec::new();
+    for entry in path.read_dir().context("failed to read directory")? {
+        let p = entry.context("failed to read entry")?.path();
+        if let Some(ext) = p.extension() {
+            if ext == "yaml" || ext == "yml" || ext == BUNDLE_EXTENSION {
+                defs.push(p);
+            }
+        }
+    }
+    defs.sort();
+
+    Ok(defs)
+}
+
+/// Load and validate processors from given path. If path not set, load default processors.
+///
+/// Bundles are verified against trusted keys. If any key is trusted, plain YAML files are rejected as unsigned.
+/// Processors extending other ones or including rule fragments are resolved against definitions of same directory.
+pub fn load(path: Option<&Path>, keys: &TrustedKeys) -> Result<Vec<Processor>> {
+    let (defs, definitions) = match path {
+        Some(path) => (discover(path)?, Definitions::read(path)?),
+        None => {
+            // Load default processors
+            (
+                vec![
+                    // include_str!("./processors/<NAME>.yaml")
+                ],
+                Definitions::default(),
+            )
+        }
+    };
+
+    let mut processors = Vec::with_capacity(defs.len());
+    for def in defs {
+        debug!("loading processor def {def:?}");
+        if def.extension().map_or(false, |ext| ext == BUNDLE_EXTENSION) {
+            let bundle = load_bundle(&def, keys)
+                .with_context(|| format!("failed to load bundle {def:?}"))?;
+            processors.extend(bundle.into_iter().map(|processor| {
+                let source = format!("{}:{}", def.display(), processor.source().unwrap_or(""));
+                processor.with_source(source)
+            }));
+
+            continue;
+        }
+
+        if !keys.is_empty() {
+            bail!("unsigned processor def {def:?} rejected as trusted keys are configured");
+        }
+
+        let processor = match definitions
+            .load(&def)
+            .with_context(|| format!("failed to load file {def:?} as processor"))?
+        {
+            Some(processor) => processor,
+            None => {
+                debug!("skipping rule library or abstract processor {def:?}");
+                continue;
+            }
+        };
+        processor
+            .validate()
+            .with_context(|| format!("processor def {def:?} is invalid"))?;
+
+        processors.push(processor.with_source(def.display().to_string()));
+    }
+    for processor in &processors {
+        redact::check_salt(processor.redaction())
+            .with_context(|| format!("processor {} can't be loaded", processor.name()))?;
+    }
+
+    Ok(processors)
+}
+
+/// Load processors from signed bundle file.
+pub fn load_bundle(path: &Path, keys: &TrustedKeys) -> Result<Vec<Processor>> {
+    let bytes = std::fs::read(path)?;
+
+    Bundle::parse(&bytes)?.verify(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{discover, load};
+    use crate::collector::bundle::{pack, tests::keypair, TrustedKeys};
+
+    fn here() -> PathBuf {
+        PathBuf::from(file!()).parent().unwrap().to_path_buf()
+    }
+
+    #[test]
+    fn discover_directory() {
+        let defs = discover(here()).unwrap();
+
+        assert_eq!(defs, vec![here().join("donuts-processor.yaml")]);
+    }
+
+    #[test]
+    fn discover_not_exists() {
+        assert!(discover(here().join("not-exists.yaml")).is_err());
+    }
+
+    #[test]
+    fn load_directory() {
+        let processors = load(Some(&here()), &TrustedKeys::default()).unwrap();
+
+        assert_eq!(processors.len(), 1);
+        assert_eq!(
+            processors[0].source(),
+            Some(
+                here()
+                    .join("donuts-processor.yaml")
+                    .display()
+                    .to_string()
+                    .as_str()
+            )
+        );
+    }
+
+    #[test]
+    fn load_default() {
+        assert!(load(None, &TrustedKeys::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn load_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("donuts.bundle");
+        let files = [(
+            "donuts.yaml",
+            include_bytes!("donuts-processor.yaml").as_slice(),
+        )];
+        std::fs::write(&path, pack(&files, &keypair(1)).unwrap()).unwrap();
+
+        let keys = TrustedKeys::from(vec![keypair(1).public]);
+        assert_eq!(load(Some(&path), &keys).unwrap().len(), 1);
+
+        // Bundles must be signed by trusted key
+        let keys = TrustedKeys::from(vec![keypair(2).public]);
+        assert!(load(Some(&path), &keys).is_err());
+    }
+
+    #[test]
+    fn load_unsigned_rejected() {
+        let keys = TrustedKeys::from(vec![keypair(1).public]);
+
+        assert!(load(Some(&here()), &keys).is_err());
+    }
+}
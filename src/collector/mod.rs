@@ -1,17 +1,49 @@
 //! Report handler module sending processed JSON documents to API endpoint
 
+pub mod archive;
+pub mod bundle;
+pub mod change;
+pub mod compose;
+pub mod dedup;
+pub mod discovery;
+pub mod drift;
+pub mod host;
+pub mod loader;
 mod processor;
+pub mod redact;
+pub mod reload;
+pub mod remote;
+pub mod replay;
+pub mod route;
+pub mod schema;
+pub mod suggest;
+pub mod template;
+pub mod testing;
+pub mod toggle;
+pub mod validate;
+pub mod version;
 
+use anyhow::Result;
 use async_trait::async_trait;
-use kkowa_proxy_lib::{http::{Response, Uri},
+use kkowa_proxy_lib::{auth::Credentials,
+                      http::{Response, Uri},
                       proxy::{Flow, Handler, Reverse}};
 use serde_json::json;
 use server_openapi::{apis::{configuration::Configuration,
                             documents_api::create_documents_api_documents_post},
                      models::CreateDocument};
-use tracing::debug;
+use tracing::{debug, warn};
 
-pub use self::processor::Processor;
+use self::{change::{Change, ChangeStore},
+           dedup::Dedup,
+           discovery::Discovery,
+           schema::DeadLetter,
+           toggle::Toggles};
+pub use self::{processor::{Output, Processor},
+               reload::{Processors, Reloader}};
+use crate::auth::user_digest;
+
+type JsonValue = serde_json::Value;
 
 /// Handler for collecting processed documents and uploading to remote server.
 #[derive(Debug)]
@@ -19,54 +51,182 @@ pub struct Collector {
     /// API endpoint URL to POST docs.
     upload_to: Option<Uri>,
 
-    /// Document processor.
-    processors: Vec<Processor>,
+    /// Document processors, may be swapped at runtime.
+    processors: Processors,
+
+    /// Store of samples of unmatched JSON flows. If not set, discovery is disabled.
+    discovery: Option<Discovery>,
+
+    /// Store of last documents of `emit: on-change` rules.
+    changes: ChangeStore,
+
+    /// Filter of duplicate uploads. If not set, every document is uploaded.
+    dedup: Option<Dedup>,
+
+    /// Global redaction rules, applied after ones of processors.
+    redact: Vec<redact::Rule>,
+
+    /// Sink of documents not conforming to output schema of processor. If not set, those are dropped.
+    dead_letter: Option<DeadLetter>,
+
+    /// Runtime overrides of enabled processors and rules.
+    toggles: Toggles,
 }
 
 impl Collector {
     /// Create new handler.
-    pub fn new(upload_to: Option<Uri>, processors: Vec<Processor>) -> Self {
+    pub fn new<P>(upload_to: Option<Uri>, processors: P) -> Self
+    where
+        P: Into<Processors>,
+    {
         Self {
             upload_to,
-            processors,
+            processors: processors.into(),
+            discovery: None,
+            changes: ChangeStore::default(),
+            dedup: None,
+            redact: vec![],
+            dead_letter: None,
+            toggles: Toggles::default(),
         }
     }
 
-    /// Generate document from HTTP flow.
-    fn process(&self, resp: &Response) -> CreateDocument {
-        let mut documents = Vec::with_capacity(self.processors.len());
-        for processor in &self.processors {
-            match processor.process(resp) {
-                Some(document) => documents.push(document),
+    /// Send documents not conforming to output schema to given sink instead of uploading.
+    pub fn dead_letter(mut self, dead_letter: DeadLetter) -> Self {
+        self.dead_letter = Some(dead_letter);
+        self
+    }
+
+    /// Apply given redaction rules to every document.
+    pub fn redact(mut self, rules: Vec<redact::Rule>) -> Self {
+        self.redact = rules;
+        self
+    }
+
+    /// Suppress uploads duplicating recent ones with given filter.
+    pub fn dedup(mut self, dedup: Dedup) -> Self {
+        self.dedup = Some(dedup);
+        self
+    }
+
+    /// Use given store for change detection, instead of default in-memory one.
+    pub fn changes(mut self, changes: ChangeStore) -> Self {
+        self.changes = changes;
+        self
+    }
+
+    /// Honour given runtime toggles of processors and rules.
+    pub fn toggles(mut self, toggles: Toggles) -> Self {
+        self.toggles = toggles;
+        self
+    }
+
+    /// Sample flows matched by no processor rule into given store.
+    pub fn discovery(mut self, discovery: Discovery) -> Self {
+        self.discovery = Some(discovery);
+        self
+    }
+
+    /// Generate document from HTTP flow of given user, along with changes of `emit: on-change` documents in it to
+    /// record once uploaded.
+    pub(crate) fn process(&self, resp: &Response, user: &str) -> (CreateDocument, Vec<Change>) {
+        let processors = self.processors.load();
+        let mut documents = Vec::with_capacity(processors.len());
+        let mut changes = Vec::new();
+        for processor in processors
+            .iter()
+            .filter(|p| self.toggles.processor_enabled(p))
+        {
+            match processor.evaluate_with(resp, user, Some(&self.changes), Some(&self.toggles)) {
+                Some(output) if output.is_unchanged() => {
+                    debug!("document unchanged since last one");
+                }
+                Some(output) if !output.errors.is_empty() => {
+                    warn!(
+                        "document of processor {name} does not conform to output schema: {errors:?}",
+                        name = processor.name(),
+                        errors = output.errors
+                    );
+                    if let Some(dead_letter) = &self.dead_letter {
+                        let url = resp.request.uri.to_string();
+                        let mut documents = output.documents();
+                        for document in &mut documents {
+                            redact::apply(&self.redact, document);
+                        }
+                        let document = match output.items.is_empty() {
+                            true => documents.remove(0),
+                            false => JsonValue::Array(documents),
+                        };
+                        if let Err(err) =
+                            dead_letter.send(processor.name(), &url, &document, &output.errors)
+                        {
+                            warn!("failed to write dead-letter document: {err:#}");
+                        }
+                    }
+                }
+                Some(output) => {
+                    for mut document in output.documents() {
+                        redact::apply(&self.redact, &mut document);
+                        documents.push(document);
+                    }
+                    changes.extend(output.changes);
+                }
                 None => {
                     debug!("document process returned nothing");
                 }
             }
         }
 
-        CreateDocument {
+        let create_document = CreateDocument {
             folder: "temp".to_string(), // TODO: Each item should be categorized by something (URL or something)
             data: Some(Some(json!(documents))),
-        }
+        };
+
+        (create_document, changes)
     }
 }
 
 #[async_trait]
 impl Handler for Collector {
-    async fn on_response(&self, flow: &Flow, resp: Response) -> Reverse {
+    async fn on_response(&self, flow: &Flow, mut resp: Response) -> Reverse {
+        host::absolutize(&mut resp.request, flow.tunnel());
+        if let Some(discovery) = &self.discovery {
+            if !self.processors.load().iter().any(|p| p.matches(&resp)) {
+                discovery.observe(&resp);
+            }
+        }
+
         if let Some(u) = &self.upload_to {
             let u = u.clone();
             if let Some(credentials) = flow.auth() {
                 let credentials = credentials.clone();
-                let create_document = self.process(&resp);
+                let user = user_digest(&credentials).unwrap_or_default();
+                let (create_document, changed) = self.process(&resp, &user);
+                if is_empty(&create_document) {
+                    debug!("no documents to upload");
+                    return Reverse::DoNothing;
+                }
+                if let (Some(dedup), Some(Some(data))) = (&self.dedup, &create_document.data) {
+                    if dedup.is_duplicate(&user, &create_document.folder, data) {
+                        return Reverse::DoNothing;
+                    }
+                }
 
+                let dedup = self.dedup.clone();
+                let changes = self.changes.clone();
                 tokio::task::spawn(async move {
-                    let cfg = Configuration {
-                        base_path: u.to_string().trim_end_matches('/').to_string(),
-                        bearer_access_token: Some(credentials.credentials().to_string()),
-                        ..Configuration::default()
-                    };
-                    create_documents_api_documents_post(&cfg, vec![create_document]).await
+                    let (folder, data) =
+                        (create_document.folder.clone(), create_document.data.clone());
+                    match upload(&u, &credentials, vec![create_document]).await {
+                        // Remember only uploaded documents, so failed ones are retried
+                        Ok(()) => {
+                            if let (Some(dedup), Some(Some(data))) = (dedup, data) {
+                                dedup.remember(&user, &folder, &data);
+                            }
+                            changes.record(&changed);
+                        }
+                        Err(err) => warn!("failed to upload document: {err:#}"),
+                    }
                 });
             }
         }
@@ -75,6 +235,31 @@ async fn on_response(&self, flow: &Flow, resp: Response) -> Reverse {
     }
 }
 
+/// Whether document has no data to upload.
+fn is_empty(document: &CreateDocument) -> bool {
+    match &document.data {
+        Some(Some(serde_json::Value::Array(data))) => data.is_empty(),
+        Some(Some(_)) => false,
+        _ => true,
+    }
+}
+
+/// Upload documents to server on behalf of user with given credentials.
+pub(crate) async fn upload(
+    upload_to: &Uri,
+    credentials: &Credentials,
+    documents: Vec<CreateDocument>,
+) -> Result<()> {
+    let cfg = Configuration {
+        base_path: upload_to.to_string().trim_end_matches('/').to_string(),
+        bearer_access_token: Some(credentials.credentials().to_string()),
+        ..Configuration::default()
+    };
+    create_documents_api_documents_post(&cfg, documents).await?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
@@ -85,7 +270,10 @@ mod tests {
     use serde_json::json;
     use server_openapi::models::CreateDocument;
 
-    use super::{Collector, Processor};
+    use super::{processor::tests::donuts,
+                schema::DeadLetter,
+                toggle::{Toggle, Toggles},
+                Collector, Processor};
 
     struct Fixture {
         mock_server: MockServer,
@@ -123,7 +311,7 @@ fn handler_process(fixture: Fixture) {
             include_bytes!("./donuts.json").to_vec(),
             req,
         );
-        let create_document = fixture.handler.process(&resp);
+        let (create_document, _) = fixture.handler.process(&resp, "user");
 
         assert_eq!(
             create_document,
@@ -156,7 +344,7 @@ fn handler_process_no_match(fixture: Fixture) {
             req,
         );
 
-        let create_document = fixture.handler.process(&resp);
+        let (create_document, _) = fixture.handler.process(&resp, "user");
 
         assert_eq!(
             create_document,
@@ -166,4 +354,117 @@ fn handler_process_no_match(fixture: Fixture) {
             }
         );
     }
+
+    #[test]
+    fn handler_process_dead_letter_redacted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dead-letter.ndjson");
+        let processor = Processor::from_str(&format!(
+            "{}\nschema:\n  required: [other]\n",
+            include_str!("./donuts-processor.yaml")
+        ))
+        .unwrap();
+        let handler = Collector::new(None, vec![processor])
+            .redact(serde_yaml::from_str("- path: extracted.donutNames\n  action: drop\n").unwrap())
+            .dead_letter(DeadLetter::open(path.clone()).unwrap());
+        let req = Request::new(
+            Method::GET,
+            Uri::from_static("http://subdomain.domain.com/donuts"),
+            Version::HTTP_11,
+            Headers::new(),
+            vec![],
+        );
+        let resp = Response::new(
+            StatusCode::OK,
+            Version::HTTP_11,
+            Headers::new(),
+            include_bytes!("./donuts.json").to_vec(),
+            req,
+        );
+
+        handler.process(&resp, "user");
+
+        let line: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(line["document"], json!({"extracted": {}}));
+    }
+
+    #[test]
+    fn handler_process_changes_not_recorded() {
+        let processor = Processor::from_str(&donuts(&[(
+            "      response:\n",
+            "      emit: on-change\n      key: [extracted.donutNames]\n\n      response:\n",
+        )]))
+        .unwrap();
+        let handler = Collector::new(None, vec![processor]);
+        let resp = || {
+            let req = Request::new(
+                Method::GET,
+                Uri::from_static("http://subdomain.domain.com/donuts"),
+                Version::HTTP_11,
+                Headers::new(),
+                vec![],
+            );
+
+            Response::new(
+                StatusCode::OK,
+                Version::HTTP_11,
+                Headers::new(),
+                include_bytes!("./donuts.json").to_vec(),
+                req,
+            )
+        };
+
+        // Emitted again until changes recorded, as if upload failed
+        let (_, changes) = handler.process(&resp(), "user");
+        let (create_document, _) = handler.process(&resp(), "user");
+        assert_ne!(create_document.data, Some(Some(json!([]))));
+
+        handler.changes.record(&changes);
+        let (create_document, changes) = handler.process(&resp(), "user");
+        assert_eq!(create_document.data, Some(Some(json!([]))));
+        assert!(changes.is_empty());
+    }
+
+    #[rstest]
+    fn handler_process_toggled(fixture: Fixture) {
+        let toggles = Toggles::new();
+        let handler = fixture.handler.toggles(toggles.clone());
+        let resp = || {
+            let req = Request::new(
+                Method::GET,
+                Uri::from_static("http://subdomain.domain.com/donuts"),
+                Version::HTTP_11,
+                Headers::new(),
+                vec![],
+            );
+
+            Response::new(
+                StatusCode::OK,
+                Version::HTTP_11,
+                Headers::new(),
+                include_bytes!("./donuts.json").to_vec(),
+                req,
+            )
+        };
+
+        for rule in [None, Some("Donuts".to_string())] {
+            let toggle = |enabled| Toggle {
+                processor: "Name".to_string(),
+                rule: rule.clone(),
+                enabled,
+            };
+            toggles.set(&toggle(Some(false))).unwrap();
+            assert_eq!(
+                handler.process(&resp(), "user").0.data,
+                Some(Some(json!([])))
+            );
+
+            toggles.set(&toggle(None)).unwrap();
+            assert_ne!(
+                handler.process(&resp(), "user").0.data,
+                Some(Some(json!([])))
+            );
+        }
+    }
 }
@@ -1,9 +1,9 @@
 //! Document processor module.
 
-use std::{path::Path, str::FromStr};
+use std::{borrow::Cow, cmp::Reverse, path::Path, str::FromStr};
 
-use anyhow::{Error, Result};
-use http::Method;
+use anyhow::{anyhow, bail, Error, Result};
+use http::{Method, Uri};
 use json_dotpath::DotPaths;
 use kkowa_proxy_lib::http::Response;
 use regex::Regex;
@@ -12,27 +12,105 @@
 use structstruck::strike;
 use tracing::{trace, warn};
 
+use super::{change::{Change, ChangeStore},
+            drift::{self, Outcome},
+            host::{HostPattern, Target},
+            redact,
+            route::{MethodMatcher, Route},
+            template::{Context, Template},
+            testing::TestCase,
+            toggle::Toggles,
+            validate::Issue,
+            version};
+
 type JsonValue = serde_json::Value;
 type JsonDotPath = String;
 type JsonPath = String;
 
+/// Prefix of selector values selecting from root of document instead of current `foreach` element.
+const ROOT: &str = "$root";
+
 // TODO: JSON schema generation
 
 strike! {
     #[strikethrough[derive(Debug, Serialize, Deserialize)]]
     pub struct Processor {
+        /// Version of definition format, legacy format if not set.
+        #[serde(rename = "apiVersion", default, skip_serializing_if = "Option::is_none")]
+        api_version: Option<String>,
+
+        /// Kind of definition.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        kind: Option<String>,
+
         metadata: struct ProcessorMetadata {
             /// Processor identifier.
             name: String,
 
+            /// Version of processor, such as `1.2.0`.
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            version: Option<String>,
+
+            /// Team or person maintaining processor.
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            owner: Option<String>,
+
+            /// Description of what processor extracts.
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            description: Option<String>,
+
+            /// Free-form tags for grouping processors.
+            #[serde(default, skip_serializing_if = "Vec::is_empty")]
+            tags: Vec<String>,
+
+            /// URL of processor documentation.
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            documentation: Option<String>,
+
+            /// Whether processor runs once loaded. Disabled processors are loaded and validated, but never run.
+            #[serde(default = "default_enabled", skip_serializing_if = "is_true")]
+            enabled: bool,
+
             /// Hostname matcher as regular expression.
-            #[serde(with = "serde_regex")]
-            hostname: Regex,
+            #[serde(default, with = "serde_regex", skip_serializing_if = "Option::is_none")]
+            hostname: Option<Regex>,
+
+            /// Hostnames or wildcard domains such as `*.example.com`, matched besides `hostname`.
+            #[serde(default, skip_serializing_if = "Vec::is_empty")]
+            hostnames: Vec<HostPattern>,
+
+            /// Schemes of flows to handle, any if empty.
+            #[serde(default, skip_serializing_if = "Vec::is_empty")]
+            schemes: Vec<String>,
+
+            /// Ports of flows to handle, any if empty.
+            #[serde(default, skip_serializing_if = "Vec::is_empty")]
+            ports: Vec<u16>,
         },
         spec: struct ProcessorSpec {
+            /// Whether to run all rules matching flow or only first one, in order of priority.
+            #[serde(default, rename = "match", skip_serializing_if = "is_default")]
+            mode: MatchMode,
+
             /// List of rules for field extraction.
             rules: Vec<SpecRule>,
         },
+
+        /// Redaction rules applied to generated documents.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        redact: Vec<redact::Rule>,
+
+        /// JSON Schema generated documents must conform to, file path relative to definition file or inline.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        schema: Option<OutputSchema>,
+
+        /// Test cases of processor.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        tests: Vec<TestCase>,
+
+        /// File processor is loaded from, if any.
+        #[serde(skip)]
+        source: Option<String>,
     }
 }
 
@@ -41,7 +119,7 @@ impl FromStr for Processor {
 
     /// Create processor from raw string config.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        serde_yaml::from_str(s)
+        Self::deserialize_versioned(serde_yaml::Deserializer::from_str(s))
     }
 }
 
@@ -51,75 +129,757 @@ pub fn from_file<P>(path: P) -> Result<Self, Error>
     where
         P: AsRef<Path>,
     {
+        let path = path.as_ref();
         let s = &std::fs::read_to_string(path)?;
-        let de = Self::from_str(s)?;
+        let mut de = Self::from_str(s)?;
+
+        let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+        de.resolve_schema(|schema| Ok(std::fs::read(base_dir.join(schema))?))?;
+
+        Ok(de)
+    }
+
+    /// Create processor from definition bytes, such as file of bundle, without reading output schema file.
+    pub(crate) fn from_slice(v: &[u8]) -> Result<Self, serde_yaml::Error> {
+        Self::deserialize_versioned(serde_yaml::Deserializer::from_slice(v))
+    }
+
+    /// Create processor from parsed definition, reading output schema file relative to given directory.
+    pub(crate) fn from_value(value: serde_yaml::Value, base_dir: &Path) -> Result<Self> {
+        let mut de = Self::deserialize_versioned(value)?;
+        de.resolve_schema(|schema| Ok(std::fs::read(base_dir.join(schema))?))?;
+
+        Ok(de)
+    }
+
+    /// Deserialize processor, rejecting unknown fields unless it is of legacy format.
+    fn deserialize_versioned<'de, D>(deserializer: D) -> Result<Self, serde_yaml::Error>
+    where
+        D: serde::Deserializer<'de, Error = serde_yaml::Error>,
+    {
+        let mut ignored = Vec::new();
+        let de: Self =
+            serde_ignored::deserialize(deserializer, |path| ignored.push(path.to_string()))?;
+        version::check(
+            de.api_version.as_deref(),
+            de.kind.as_deref(),
+            de.name(),
+            &ignored,
+        )
+        .map_err(serde::de::Error::custom)?;
 
         Ok(de)
     }
 
-    /// Process given JSON document with processor's rule and generate new JSON document.
+    /// Read and compile output schema file, if any, with given reader taking path of schema file.
+    pub(crate) fn resolve_schema<F>(&mut self, read: F) -> Result<()>
+    where
+        F: FnOnce(&str) -> Result<Vec<u8>>,
+    {
+        match &mut self.schema {
+            Some(schema) => schema.resolve(read),
+            None => Ok(()),
+        }
+    }
+
+    /// Name of processor.
+    pub fn name(&self) -> &str {
+        &self.metadata.name
+    }
+
+    /// Whether processor is enabled by default.
+    pub fn is_enabled(&self) -> bool {
+        self.metadata.enabled
+    }
+
+    /// File processor is loaded from, if any.
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    /// Set file processor is loaded from.
+    pub(crate) fn with_source<S>(mut self, source: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// Names of rules, in file order. Unnamed rules are named by their index as `#<index>`.
+    pub fn rule_names(&self) -> Vec<String> {
+        self.spec
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(index, rule)| rule.label(index))
+            .collect()
+    }
+
+    /// Summary of processor for listing, with metadata, rules and source file.
+    pub fn summary(&self) -> JsonValue {
+        let metadata = &self.metadata;
+        let rules: Vec<JsonValue> = self
+            .spec
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(index, rule)| {
+                json!({
+                    "name": rule.label(index),
+                    "description": rule.description,
+                })
+            })
+            .collect();
+
+        json!({
+            "name": metadata.name,
+            "version": metadata.version,
+            "owner": metadata.owner,
+            "description": metadata.description,
+            "tags": metadata.tags,
+            "documentation": metadata.documentation,
+            "enabled": metadata.enabled,
+            "hostname": metadata.hostname.as_ref().map(Regex::as_str),
+            "hostnames": metadata.hostnames,
+            "source": self.source,
+            "rules": rules,
+        })
+    }
+
+    /// Redaction rules of processor.
+    pub(crate) fn redaction(&self) -> &[redact::Rule] {
+        &self.redact
+    }
+
+    /// Test cases embedded in processor definition.
+    pub(crate) fn tests(&self) -> &[TestCase] {
+        &self.tests
+    }
+
+    /// Check processor definition is valid, such as expressions of selectors. Warnings are ignored.
+    pub fn validate(&self) -> Result<()> {
+        match self.issues().into_iter().find(|issue| !issue.warning) {
+            Some(issue) => bail!("{issue}"),
+            None => Ok(()),
+        }
+    }
+
+    /// Find all problems of processor definition which could not be caught while deserialization.
+    pub(crate) fn issues(&self) -> Vec<Issue> {
+        let mut issues = Vec::new();
+        if self.metadata.hostname.is_none() && self.metadata.hostnames.is_empty() {
+            issues.push(Issue::new("processor must have hostname or hostnames"));
+        }
+        if let Some(url) = &self.metadata.documentation {
+            let valid = url.parse::<Uri>().map_or(false, |uri| {
+                matches!(uri.scheme_str(), Some("http" | "https"))
+            });
+            if !valid {
+                issues.push(Issue::new(format!(
+                    "documentation must be http(s) URL, but got `{url}`"
+                )));
+            }
+        }
+        for rule in &self.redact {
+            if let Err(err) = rule.validate() {
+                issues.push(Issue::new(format!("invalid redaction rule: {err}")));
+            }
+        }
+        for (index, rule) in self.spec.rules.iter().enumerate() {
+            match (&rule.method, rule.methods.is_empty()) {
+                (Some(_), true) | (None, false) => {}
+                _ => issues.push(
+                    Issue::new("rule must have either method or methods")
+                        .rule(index, rule.label(index)),
+                ),
+            }
+            match (&rule.path, &rule.route) {
+                (Some(_), None) | (None, Some(_)) => {}
+                _ => issues.push(
+                    Issue::new("rule must have either path or route")
+                        .rule(index, rule.label(index)),
+                ),
+            }
+            if let Some(foreach) = &rule.foreach {
+                if let Err(err) = jsonpath_lib::Compiled::compile(foreach) {
+                    issues.push(
+                        Issue::new(format!("invalid foreach JsonPath `{foreach}`: {err}"))
+                            .rule(index, rule.label(index))
+                            .hint(foreach),
+                    );
+                }
+            }
+            if rule.emit == Emit::OnChange && rule.key.is_empty() {
+                issues.push(
+                    Issue::new(
+                        "rule with `emit: on-change` has no key, so all its documents are compared as one",
+                    )
+                    .rule(index, rule.label(index))
+                    .warning(),
+                );
+            }
+            if let Some(Err(err)) = rule.output.as_ref().map(Template::validate) {
+                issues.push(
+                    Issue::new(format!("invalid output template: {err}"))
+                        .rule(index, rule.label(index)),
+                );
+            }
+            for selector in rule
+                .request
+                .selectors
+                .iter()
+                .chain(&rule.response.selectors)
+            {
+                if let Err(err) = selector.validate() {
+                    issues.push(
+                        Issue::new(err.to_string())
+                            .rule(index, rule.label(index))
+                            .hint(&selector.value),
+                    );
+                }
+            }
+        }
+        issues.extend(self.conflicts());
+        issues.extend(self.shadowed());
+
+        issues
+    }
+
+    /// Find rules never run as other rule evaluated before matches all flows those match, in `match: first` mode.
+    fn shadowed(&self) -> Vec<Issue> {
+        if self.spec.mode != MatchMode::First {
+            return vec![];
+        }
+
+        let rules = self.ordered_rules();
+        let mut issues = Vec::new();
+        for (i, &(index, rule)) in rules.iter().enumerate() {
+            if let Some(&(other, _)) = rules[..i].iter().find(|(_, before)| before.shadows(rule)) {
+                issues.push(
+                    Issue::new(format!(
+                        "rule is shadowed by rule {} of higher priority and never runs",
+                        self.spec.rules[other].label(other)
+                    ))
+                    .rule(index, rule.label(index))
+                    .hint(rule.pattern_source())
+                    .warning(),
+                );
+            }
+        }
+
+        issues
+    }
+
+    /// Rules with their indices in order of evaluation; by priority, highest first, then in file order.
+    fn ordered_rules(&self) -> Vec<(usize, &SpecRule)> {
+        let mut rules: Vec<_> = self.spec.rules.iter().enumerate().collect();
+        rules.sort_by_key(|(_, rule)| Reverse(rule.priority));
+        rules
+    }
+
+    /// Find fields written by several selectors or rules generating same document, where none of them declares
+    /// merge strategy explicitly.
+    fn conflicts(&self) -> Vec<Issue> {
+        // Rule index, field and whether merge strategy declared
+        let mut writes: Vec<(usize, &str, bool)> = Vec::new();
+        for (index, rule) in self.spec.rules.iter().enumerate() {
+            let explicit = rule.merge != Strategy::default();
+            match &rule.output {
+                Some(template) => {
+                    writes.extend(template.fields().into_iter().map(|f| (index, f, explicit)))
+                }
+                None => writes.extend(
+                    rule.request
+                        .selectors
+                        .iter()
+                        .chain(&rule.response.selectors)
+                        .map(|s| (index, s.key.as_str(), explicit || s.merge.is_some())),
+                ),
+            }
+        }
+
+        let rules = &self.spec.rules;
+        let mut issues = Vec::new();
+        for (i, &(index, field, explicit)) in writes.iter().enumerate() {
+            let conflict = writes[..i]
+                .iter()
+                .find(|&&(other, other_field, other_explicit)| {
+                    // Documents of `foreach` rules are separate from others, and only one rule runs in `match: first`
+                    // mode
+                    let same_document = other == index
+                        || (self.spec.mode != MatchMode::First
+                            && rules[other].foreach.is_none()
+                            && rules[index].foreach.is_none()
+                            && rules[other].overlaps(&rules[index]));
+
+                    same_document
+                        && !(explicit || other_explicit)
+                        && (field == other_field
+                            || is_nested(field, other_field)
+                            || is_nested(other_field, field))
+                });
+            if let Some(&(other, other_field, _)) = conflict {
+                let by = match other == index {
+                    true => "another selector".to_string(),
+                    false => format!("rule {}", rules[other].label(other)),
+                };
+                issues.push(
+                    Issue::new(format!(
+                        "field `{field}` conflicts with `{other_field}` written by {by}; declare `merge` strategy"
+                    ))
+                    .rule(index, rules[index].label(index))
+                    .hint(field)
+                    .warning(),
+                );
+            }
+        }
+
+        issues
+    }
+
+    /// Check each rule of processor definition could be deserialized, to report schema errors per rule.
+    pub(crate) fn rule_errors(value: &serde_yaml::Value) -> Vec<(usize, serde_yaml::Error)> {
+        let rules = match value.get("spec").and_then(|spec| spec.get("rules")) {
+            Some(serde_yaml::Value::Sequence(rules)) => rules,
+            _ => return vec![],
+        };
+
+        rules
+            .iter()
+            .enumerate()
+            .filter_map(|(index, rule)| {
+                serde_yaml::from_value::<SpecRule>(rule.clone())
+                    .err()
+                    .map(|err| (index, err))
+            })
+            .collect()
+    }
+
+    /// Check whether processor handles flows of given host.
+    pub fn matches_host(&self, host: &str) -> bool {
+        let metadata = &self.metadata;
+        let host = host.to_ascii_lowercase();
+
+        metadata
+            .hostname
+            .as_ref()
+            .map_or(false, |hostname| hostname.is_match(&host))
+            || metadata
+                .hostnames
+                .iter()
+                .any(|pattern| pattern.matches(&host))
+    }
+
+    /// Check whether processor handles flows to given target, by host, scheme and port.
+    pub fn matches_target(&self, target: &Target) -> bool {
+        let metadata = &self.metadata;
+        let scheme_matches = metadata.schemes.is_empty()
+            || target.scheme.as_ref().map_or(false, |scheme| {
+                metadata
+                    .schemes
+                    .iter()
+                    .any(|s| s.eq_ignore_ascii_case(scheme))
+            });
+        let port_matches = metadata.ports.is_empty()
+            || target
+                .port
+                .map_or(false, |port| metadata.ports.contains(&port));
+
+        scheme_matches && port_matches && self.matches_host(&target.host)
+    }
+
+    /// Check whether any rule of processor matches given flow, without running selectors.
+    pub fn matches(&self, resp: &Response) -> bool {
+        let req = &resp.request;
+        Target::of(req).map_or(false, |target| self.matches_target(&target))
+            && self.spec.rules.iter().any(|rule| {
+                rule.matches_method(&req.method)
+                    && rule
+                        .pattern()
+                        .map_or(false, |pattern| pattern.is_match(req.uri.path()))
+            })
+    }
+
+    /// Process given JSON document with processor's rule and generate new JSON document. If rules with `foreach`
+    /// generated documents, array of those is returned.
     pub fn process(&self, resp: &Response) -> Option<JsonValue> {
+        self.evaluate(resp).map(Output::into_value)
+    }
+
+    /// Process given JSON document like [`Processor::process`], also reporting rules matched.
+    pub fn evaluate(&self, resp: &Response) -> Option<Output> {
+        self.evaluate_with(resp, "", None, None)
+    }
+
+    /// Process given JSON document like [`Processor::evaluate`], leaving out documents of `emit: on-change` rules
+    /// unchanged since last time for given user according to given store. Changes are not recorded to store; record
+    /// [`Output::changes`] once documents delivered.
+    pub fn evaluate_with(
+        &self,
+        resp: &Response,
+        user: &str,
+        changes: Option<&ChangeStore>,
+        toggles: Option<&Toggles>,
+    ) -> Option<Output> {
         let req = &resp.request;
 
-        let hostname = req.uri.host().unwrap();
-        if !self.metadata.hostname.is_match(hostname) {
+        let target = match Target::of(req) {
+            Some(target) => target,
+            None => {
+                trace!(
+                    "can't resolve target host of request to {uri}",
+                    uri = req.uri
+                );
+                return None;
+            }
+        };
+        if !self.matches_target(&target) {
             trace!(
-                r#"hostname "{hostname}" does not match to regular expression `{regex}`"#,
-                regex = self.metadata.hostname
+                r#"target "{host}" does not match to hosts, schemes or ports of processor"#,
+                host = target.host
             );
 
             return None;
         }
 
         let mut result = json!({});
-        for rule in &self.spec.rules {
+        let mut items = Vec::new();
+        let mut matched = Vec::new();
+        let mut unchanged = Vec::new();
+        let mut changed = Vec::new();
+        for (index, rule) in self.ordered_rules() {
+            let label = rule.label(index);
+            if let Some(false) = toggles.map(|t| t.rule_enabled(self.name(), &label)) {
+                trace!("rule {label} is disabled");
+                continue;
+            }
+
             // Check HTTP method
             let method = &req.method;
-            if rule.method != method {
-                trace!(
-                    r#"expected method "{expected}" but got "{method}""#,
-                    expected = rule.method
-                );
+            if !rule.matches_method(method) {
+                trace!(r#"method "{method}" does not match to methods of rule"#);
                 continue;
             }
 
+            let pattern = match rule.pattern() {
+                Some(pattern) => pattern,
+                None => continue,
+            };
             let path = req.uri.path();
-            if !rule.path.is_match(path) {
+            if !pattern.is_match(path) {
                 trace!(
                     r#"path "{path}" does not match to regular expression `{regex}`"#,
-                    regex = rule.path
+                    regex = pattern
                 );
                 continue;
             }
 
-            // Select fields from request
-            if !rule.request.selectors.is_empty() {
-                let maybe_document = JsonValue::from_str(&String::from_utf8_lossy(&req.payload));
-                match maybe_document {
-                    Ok(obj) => {
-                        for selector in &rule.request.selectors {
-                            selector.insert(&obj, &mut result);
-                        }
+            let mut fragments = self.fragments(&label, rule, resp);
+            if let Some(template) = &rule.output {
+                let context = Context::new(resp, pattern);
+                for fragment in fragments.iter_mut() {
+                    *fragment = template.render(fragment, &context);
+                }
+            }
+            let mut emitted = 0;
+            for fragment in fragments.iter() {
+                if let (Emit::OnChange, Some(changes)) = (&rule.emit, changes) {
+                    let key = rule.key_of(fragment);
+                    match changes.check(user, self.name(), &label, &key, fragment) {
+                        Some(change) => changed.push(change),
+                        None => continue,
                     }
-                    Err(err) => warn!("can't parse JSON from request body: {err}"),
                 }
+
+                emitted += 1;
+                match rule.foreach {
+                    Some(_) => items.push(fragment.clone()),
+                    None => rule.merge_into(&mut result, fragment.clone()),
+                }
+            }
+            if emitted == 0 && !fragments.is_empty() {
+                trace!("documents of rule {label} unchanged");
+                unchanged.push(label.clone());
             }
+            matched.push(label);
 
-            // Select fields from response
-            if !rule.response.selectors.is_empty() {
-                let maybe_document = JsonValue::from_str(&String::from_utf8_lossy(&resp.payload));
-                match maybe_document {
-                    Ok(obj) => {
-                        for selector in &rule.response.selectors {
-                            selector.insert(&obj, &mut result);
-                        }
-                    }
-                    Err(err) => warn!("can't parse JSON from response body: {err}"),
+            if self.spec.mode == MatchMode::First {
+                break;
+            }
+        }
+
+        redact::apply(&self.redact, &mut result);
+        for item in items.iter_mut() {
+            redact::apply(&self.redact, item);
+        }
+
+        let mut output = Output {
+            document: result,
+            items,
+            rules: matched,
+            unchanged,
+            changes: changed,
+            errors: vec![],
+        };
+        if let Some(schema) = &self.schema {
+            if !output.rules.is_empty() && !output.is_unchanged() {
+                for document in output.documents() {
+                    output.errors.extend(schema.validate(&document));
+                }
+            }
+        }
+
+        Some(output)
+    }
+
+    /// Select documents of rule; one for each element selected by `foreach`, or single one if not set.
+    fn fragments(&self, label: &str, rule: &SpecRule, resp: &Response) -> Vec<JsonValue> {
+        let req = &resp.request;
+        let mut base = json!({});
+        self.select(
+            label,
+            rule.merge,
+            &rule.request.selectors,
+            &req.payload,
+            &mut base,
+            "request",
+        );
+
+        let foreach = match &rule.foreach {
+            Some(foreach) => foreach,
+            None => {
+                self.select(
+                    label,
+                    rule.merge,
+                    &rule.response.selectors,
+                    &resp.payload,
+                    &mut base,
+                    "response",
+                );
+                return vec![base];
+            }
+        };
+
+        let body = match JsonValue::from_str(&String::from_utf8_lossy(&resp.payload)) {
+            Ok(body) => body,
+            Err(err) => {
+                warn!("can't parse JSON from response body: {err}");
+                for selector in &rule.response.selectors {
+                    drift::record(self.name(), label, &selector.key, Outcome::Error);
+                }
+                return vec![];
+            }
+        };
+        let elements = match jsonpath_lib::select(&body, foreach) {
+            Ok(elements) => elements,
+            Err(err) => {
+                warn!("foreach `{foreach}` failed: {err:?}");
+                return vec![];
+            }
+        };
+
+        elements
+            .into_iter()
+            .map(|element| {
+                let mut document = base.clone();
+                self.apply(
+                    label,
+                    rule.merge,
+                    &rule.response.selectors,
+                    element,
+                    &body,
+                    &mut document,
+                );
+                document
+            })
+            .collect()
+    }
+
+    /// Run selectors of rule against JSON payload of request or response, recording outcome of each.
+    fn select(
+        &self,
+        rule: &str,
+        strategy: Strategy,
+        selectors: &[Selector],
+        payload: &[u8],
+        result: &mut JsonValue,
+        from: &str,
+    ) {
+        if selectors.is_empty() {
+            return;
+        }
+
+        match JsonValue::from_str(&String::from_utf8_lossy(payload)) {
+            Ok(obj) => self.apply(rule, strategy, selectors, &obj, &obj, result),
+            Err(err) => {
+                warn!("can't parse JSON from {from} body: {err}");
+                for selector in selectors {
+                    drift::record(self.name(), rule, &selector.key, Outcome::Error);
                 }
             }
         }
+    }
+
+    /// Run selectors against element of JSON document, recording outcome of each.
+    fn apply(
+        &self,
+        rule: &str,
+        strategy: Strategy,
+        selectors: &[Selector],
+        element: &JsonValue,
+        root: &JsonValue,
+        result: &mut JsonValue,
+    ) {
+        for selector in selectors {
+            let outcome = selector.insert(element, root, result, strategy);
+            drift::record(self.name(), rule, &selector.key, outcome);
+        }
+    }
+}
+
+/// Document(s) generated by processor.
+#[derive(Debug)]
+pub struct Output {
+    /// Document generated by rules without `foreach`.
+    pub document: JsonValue,
+
+    /// Documents generated for each element by rules with `foreach`.
+    pub items: Vec<JsonValue>,
+
+    /// Labels of rules matched, name of rule or its index if not named.
+    pub rules: Vec<String>,
+
+    /// Labels of `emit: on-change` rules matched but left out as their documents unchanged.
+    pub unchanged: Vec<String>,
+
+    /// Changes of `emit: on-change` documents emitted, to record once delivered.
+    pub changes: Vec<Change>,
+
+    /// Errors of validation against output schema.
+    pub errors: Vec<String>,
+}
+
+impl Output {
+    /// Documents to emit; each of items merged over document, or document itself if there are no items.
+    pub fn documents(&self) -> Vec<JsonValue> {
+        if self.items.is_empty() {
+            return vec![self.document.clone()];
+        }
+
+        self.items
+            .iter()
+            .map(|item| {
+                let mut document = self.document.clone();
+                merge(&mut document, item.clone());
+                document
+            })
+            .collect()
+    }
+
+    /// Generated document, or array of documents if processor has rules with `foreach` generating any.
+    pub fn into_value(self) -> JsonValue {
+        match self.items.is_empty() {
+            true => self.document,
+            false => JsonValue::Array(self.documents()),
+        }
+    }
 
-        Some(result)
+    /// Whether all rules matched were left out as unchanged, so there is nothing to emit.
+    pub fn is_unchanged(&self) -> bool {
+        !self.unchanged.is_empty() && self.unchanged.len() == self.rules.len()
+    }
+}
+
+/// Which of rules matching flow to run.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum MatchMode {
+    /// Run all rules matched.
+    #[default]
+    All,
+
+    /// Run only first rule matched.
+    First,
+}
+
+/// When to emit documents generated by rule.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Emit {
+    /// Emit every document.
+    #[default]
+    Always,
+
+    /// Emit documents only if new or changed since last one of same key.
+    OnChange,
+}
+
+/// How to merge value written to field already having value.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Strategy {
+    /// Replace existing value.
+    #[default]
+    Overwrite,
+
+    /// Concatenate values into array.
+    Append,
+
+    /// Merge object fields recursively, replacing other values.
+    DeepMerge,
+
+    /// Keep existing value.
+    KeepFirst,
+}
+
+impl Strategy {
+    /// Combine existing value of field with new one. `null` and empty arrays are treated as no value.
+    fn combine(self, existing: Option<JsonValue>, new: JsonValue) -> JsonValue {
+        let existing = match existing {
+            Some(JsonValue::Null) | None => return new,
+            Some(JsonValue::Array(items)) if items.is_empty() => return new,
+            Some(existing) => existing,
+        };
+
+        match self {
+            Self::Overwrite => new,
+            Self::Append => {
+                let mut items = into_items(existing);
+                items.extend(into_items(new));
+                JsonValue::Array(items)
+            }
+            Self::DeepMerge => {
+                let mut existing = existing;
+                merge(&mut existing, new);
+                existing
+            }
+            Self::KeepFirst => existing,
+        }
+    }
+}
+
+fn into_items(value: JsonValue) -> Vec<JsonValue> {
+    match value {
+        JsonValue::Array(items) => items,
+        other => vec![other],
+    }
+}
+
+/// Merge object fields of `from` into `into` recursively, overwriting other values.
+fn merge(into: &mut JsonValue, from: JsonValue) {
+    match (into, from) {
+        (JsonValue::Object(into), JsonValue::Object(from)) => {
+            for (key, value) in from {
+                match into.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        into.insert(key, value);
+                    }
+                }
+            }
+        }
+        (into, from) => *into = from,
     }
 }
 
@@ -132,13 +892,30 @@ struct SpecRule {
         /// Additional description of rule.
         description: Option<String>,
 
-        /// Request method matcher.
-        #[serde(with = "http_serde::method")]
-        method: Method,
+        /// Priority of rule. Rules of higher priority run first, and rules of same priority in file order.
+        #[serde(default, skip_serializing_if = "is_default")]
+        priority: i32,
+
+        /// Request method matcher, `*` for any method.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        method: Option<MethodMatcher>,
+
+        /// Request method matchers, alternative to `method`.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        methods: Vec<MethodMatcher>,
+
+        /// JsonPath of response body elements to generate document for each. Response selectors are evaluated
+        /// relative to each element, where `$root` refers to whole body.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        foreach: Option<JsonPath>,
 
         /// Path component matcher for flow.
-        #[serde(with = "serde_regex")]
-        path: Regex,
+        #[serde(default, with = "serde_regex", skip_serializing_if = "Option::is_none")]
+        path: Option<Regex>,
+
+        /// Route template of path, alternative to `path`. Parameters are available to output template as captures.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        route: Option<Route>,
 
         /// Request process rule.
         request: struct SpecRuleRequest {
@@ -151,34 +928,298 @@ struct SpecRule {
             /// List of field selectors.
             selectors: Vec<Selector>,
         },
+
+        /// When to emit documents of rule.
+        #[serde(default, skip_serializing_if = "is_default")]
+        emit: Emit,
+
+        /// Fields of document identifying it for change detection, such as product ID.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        key: Vec<JsonDotPath>,
+
+        /// Template of document rendered from selector results, replacing them.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        output: Option<Template>,
+
+        /// Default merge strategy of fields written by rule.
+        #[serde(default, skip_serializing_if = "is_default")]
+        merge: Strategy,
     }
 }
 
+impl SpecRule {
+    /// Label of rule for reporting, name of rule or its index if not named.
+    fn label(&self, index: usize) -> String {
+        self.name.clone().unwrap_or_else(|| format!("#{index}"))
+    }
+
+    /// Fields written by rule, as dot paths with merge strategies. Fields nested in another one are left out.
+    fn fields(&self, document: &JsonValue) -> Vec<(String, Strategy)> {
+        let fields: Vec<(String, Strategy)> = match (&self.output, document) {
+            (Some(_), JsonValue::Object(map)) => {
+                map.keys().map(|key| (key.clone(), self.merge)).collect()
+            }
+            (Some(_), _) => vec![],
+            (None, _) => self
+                .request
+                .selectors
+                .iter()
+                .chain(&self.response.selectors)
+                .map(|selector| (selector.key.clone(), selector.merge.unwrap_or(self.merge)))
+                .collect(),
+        };
+
+        let mut unique: Vec<(String, Strategy)> = Vec::new();
+        for (path, strategy) in fields {
+            if !fields_overlap(&unique, &path) {
+                unique.retain(|(other, _)| !is_nested(other, &path));
+                unique.push((path, strategy));
+            }
+        }
+
+        unique
+    }
+
+    /// Merge document of rule into document of processor, resolving conflicting fields with merge strategies.
+    fn merge_into(&self, result: &mut JsonValue, document: JsonValue) {
+        if self.output.is_some() && !document.is_object() {
+            *result = document;
+            return;
+        }
+
+        for (path, strategy) in self.fields(&document) {
+            let value = match document.dot_get::<JsonValue>(&path) {
+                Ok(Some(value)) => value,
+                _ => continue,
+            };
+            let existing = result.dot_get::<JsonValue>(&path).ok().flatten();
+            if let Err(err) = result.dot_set(&path, strategy.combine(existing, value)) {
+                warn!("failed to merge document field `{path}`: {err}");
+            }
+        }
+    }
+
+    /// Whether rule matches all flows other rule matches.
+    fn shadows(&self, other: &SpecRule) -> bool {
+        let covered = other
+            .method_matchers()
+            .all(|theirs| self.method_matchers().any(|ours| ours.covers(theirs)));
+
+        covered && self.covers_path(other)
+    }
+
+    /// Whether rule matches all paths other rule matches. Only identical path patterns, or patterns of other being
+    /// anchored literal path, are compared.
+    fn covers_path(&self, other: &SpecRule) -> bool {
+        let (ours, theirs) = match (self.pattern(), other.pattern()) {
+            (Some(ours), Some(theirs)) => (ours, theirs),
+            _ => return false,
+        };
+        if ours.as_str() == theirs.as_str() {
+            return true;
+        }
+
+        match theirs
+            .as_str()
+            .strip_prefix('^')
+            .and_then(|path| path.strip_suffix('$'))
+        {
+            Some(literal) if regex::escape(literal) == literal => ours.is_match(literal),
+            _ => false,
+        }
+    }
+
+    /// Method matchers of rule, from either `method` or `methods`.
+    fn method_matchers(&self) -> impl Iterator<Item = &MethodMatcher> {
+        self.method.iter().chain(&self.methods)
+    }
+
+    /// Check rule matches request method.
+    fn matches_method(&self, method: &Method) -> bool {
+        self.method_matchers()
+            .any(|matcher| matcher.matches(method))
+    }
+
+    /// Whether both rules match some same flow, as far as can be told from patterns.
+    fn overlaps(&self, other: &SpecRule) -> bool {
+        self.overlaps_methods(other) && (self.covers_path(other) || other.covers_path(self))
+    }
+
+    /// Whether any request method is matched by both rules.
+    fn overlaps_methods(&self, other: &SpecRule) -> bool {
+        self.method_matchers().any(|ours| {
+            other
+                .method_matchers()
+                .any(|theirs| ours.covers(theirs) || theirs.covers(ours))
+        })
+    }
+
+    /// Path matcher of rule, from either `path` or `route`.
+    fn pattern(&self) -> Option<&Regex> {
+        self.path
+            .as_ref()
+            .or_else(|| self.route.as_ref().map(Route::regex))
+    }
+
+    /// Path matcher as written in definition.
+    fn pattern_source(&self) -> &str {
+        match (&self.path, &self.route) {
+            (Some(path), _) => path.as_str(),
+            (None, Some(route)) => route.as_str(),
+            (None, None) => "",
+        }
+    }
+
+    /// Values of key fields of document.
+    fn key_of(&self, document: &JsonValue) -> JsonValue {
+        self.key
+            .iter()
+            .map(|path| {
+                document
+                    .dot_get::<JsonValue>(path)
+                    .ok()
+                    .flatten()
+                    .unwrap_or(JsonValue::Null)
+            })
+            .collect()
+    }
+}
+
+/// Whether `path` equals to or is nested in any of fields.
+fn fields_overlap(fields: &[(String, Strategy)], path: &str) -> bool {
+    fields
+        .iter()
+        .any(|(field, _)| field == path || is_nested(path, field))
+}
+
+/// Whether dot path `path` is nested in `parent`.
+fn is_nested(path: &str, parent: &str) -> bool {
+    path.strip_prefix(parent)
+        .map_or(false, |rest| rest.starts_with('.'))
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+fn is_default<T>(value: &T) -> bool
+where
+    T: Default + PartialEq,
+{
+    value == &T::default()
+}
+
 // TODO: Validation for JsonDotPath
-// TODO: Validation for JsonPath and path pre-compilation
 #[derive(Debug, Serialize, Deserialize)]
 struct Selector {
     key: JsonDotPath,
     value: JsonPath,
+
+    /// Merge strategy of field, overriding one of rule.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    merge: Option<Strategy>,
 }
 
 impl Selector {
-    fn insert(&self, select_from: &JsonValue, insert_to: &mut JsonValue) {
-        let selector = jsonpath_lib::Compiled::compile(&self.value).unwrap();
-        let new = selector.select(select_from).unwrap();
-        insert_to.dot_set(&self.key, new).unwrap();
+    fn validate(&self) -> Result<()> {
+        if self.key.is_empty() {
+            bail!("selector key must not be empty");
+        }
+        let (_, expression) = self.expression();
+        jsonpath_lib::Compiled::compile(&expression)
+            .map_err(|err| anyhow!("invalid JsonPath `{value}`: {err}", value = self.value))?;
+
+        Ok(())
+    }
+
+    /// JsonPath expression of selector, and whether it selects from root instead of current element.
+    fn expression(&self) -> (bool, Cow<str>) {
+        match self.value.strip_prefix(ROOT) {
+            Some(rest) if rest.is_empty() || rest.starts_with(['.', '[']) => {
+                (true, Cow::Owned(format!("${rest}")))
+            }
+            _ => (false, Cow::Borrowed(&self.value)),
+        }
+    }
+
+    /// Select values from element, or root if selector refers to it, and insert those to document. Existing value of
+    /// field is merged with selector's strategy, or given default one.
+    fn insert(
+        &self,
+        element: &JsonValue,
+        root: &JsonValue,
+        insert_to: &mut JsonValue,
+        default: Strategy,
+    ) -> Outcome {
+        let (from_root, expression) = self.expression();
+        let select_from = match from_root {
+            true => root,
+            false => element,
+        };
+        let selector = match jsonpath_lib::Compiled::compile(&expression) {
+            Ok(selector) => selector,
+            Err(err) => {
+                warn!("invalid JsonPath `{value}`: {err}", value = self.value);
+                return Outcome::Error;
+            }
+        };
+        let new = match selector.select(select_from) {
+            Ok(new) => new,
+            Err(err) => {
+                warn!("selector `{value}` failed: {err:?}", value = self.value);
+                return Outcome::Error;
+            }
+        };
+
+        let outcome = match new.is_empty() {
+            true => Outcome::Empty,
+            false => Outcome::Match,
+        };
+        let existing = insert_to.dot_get::<JsonValue>(&self.key).ok().flatten();
+        let strategy = self.merge.unwrap_or(default);
+        if let Err(err) = insert_to.dot_set(&self.key, strategy.combine(existing, json!(new))) {
+            warn!(
+                "failed to insert selected values to `{key}`: {err}",
+                key = self.key
+            );
+            return Outcome::Error;
+        }
+
+        outcome
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use std::str::FromStr;
 
     use http::Uri;
     use kkowa_proxy_lib::http::{Request, Response};
+    use rstest::*;
     use serde_json::json;
 
-    use super::{Processor, Selector};
+    use super::{merge, Outcome, Processor, Selector, Strategy};
+    use crate::collector::change::ChangeStore;
+
+    /// Replace text in definition source, failing if any of texts to replace is not found.
+    pub(crate) fn substitute(source: &str, substitutions: &[(&str, &str)]) -> String {
+        let mut source = source.to_string();
+        for (from, to) in substitutions {
+            assert!(source.contains(from), "{from:?} not found in definition");
+            source = source.replace(from, to);
+        }
+
+        source
+    }
+
+    /// Sample donuts processor definition with given substitutions.
+    pub(crate) fn donuts(substitutions: &[(&str, &str)]) -> String {
+        substitute(include_str!("donuts-processor.yaml"), substitutions)
+    }
 
     #[test]
     fn processor_from_str() {
@@ -196,6 +1237,40 @@ fn processor_from_file() {
         .unwrap();
     }
 
+    #[test]
+    fn processor_validate() {
+        let processor = Processor::from_str(include_str!("donuts-processor.yaml")).unwrap();
+        assert!(processor.validate().is_ok());
+
+        let processor = Processor::from_str(&donuts(&[("$[*].name", "$[*")])).unwrap();
+        assert!(processor.validate().is_err());
+    }
+
+    #[test]
+    fn processor_metadata() {
+        let source = donuts(&[(
+            "  name: Name\n",
+            "  name: Name\n  version: 1.2.0\n  owner: shop-team\n  tags: [shop]\n  documentation: https://docs.domain.com/donuts\n  enabled: false\n",
+        )]);
+        let processor = Processor::from_str(&source).unwrap();
+        assert!(processor.validate().is_ok());
+        assert!(!processor.is_enabled());
+
+        let summary = processor.with_source("donuts-processor.yaml").summary();
+        assert_eq!(summary["version"], "1.2.0");
+        assert_eq!(summary["owner"], "shop-team");
+        assert_eq!(summary["tags"], json!(["shop"]));
+        assert_eq!(summary["source"], "donuts-processor.yaml");
+        assert_eq!(summary["rules"][0]["name"], "Donuts");
+
+        let processor = Processor::from_str(&substitute(
+            &source,
+            &[("https://docs.domain.com/donuts", "docs")],
+        ))
+        .unwrap();
+        assert!(processor.validate().is_err());
+    }
+
     #[test]
     fn processor_process() {
         // Test with sample processor def file
@@ -223,6 +1298,397 @@ fn processor_process() {
         )
     }
 
+    #[rstest]
+    #[case("http://subdomain.domain.com/donuts", true)]
+    #[case("http://subdomain.domain.com/cakes", false)]
+    #[case("http://domain.com/donuts", false)]
+    fn processor_matches(#[case] uri: &'static str, #[case] expected: bool) {
+        let processor = Processor::from_str(include_str!("donuts-processor.yaml")).unwrap();
+        let req = Request::builder()
+            .uri(Uri::from_static(uri))
+            .build()
+            .unwrap();
+        let resp = Response::builder().request(req).build().unwrap();
+
+        assert_eq!(processor.matches(&resp), expected);
+    }
+
+    #[test]
+    fn processor_evaluate() {
+        let processor = Processor::from_str(include_str!("donuts-processor.yaml")).unwrap();
+        let req = Request::builder()
+            .uri(Uri::from_static("http://subdomain.domain.com/donuts"))
+            .build()
+            .unwrap();
+
+        let resp = Response::builder()
+            .payload(include_bytes!("./donuts.json").to_vec())
+            .request(req)
+            .build()
+            .unwrap();
+
+        let output = processor.evaluate(&resp).unwrap();
+
+        assert_eq!(output.rules, vec!["Donuts".to_string()]);
+    }
+
+    #[test]
+    fn processor_evaluate_on_change() {
+        let processor = Processor::from_str(&donuts(&[(
+            "      response:\n",
+            "      emit: on-change\n      key: [extracted.donutNames]\n\n      response:\n",
+        )]))
+        .unwrap();
+        let changes = ChangeStore::default();
+        let resp = || {
+            let req = Request::builder()
+                .uri(Uri::from_static("http://subdomain.domain.com/donuts"))
+                .build()
+                .unwrap();
+
+            Response::builder()
+                .payload(include_bytes!("./donuts.json").to_vec())
+                .request(req)
+                .build()
+                .unwrap()
+        };
+
+        let output = processor
+            .evaluate_with(&resp(), "user", Some(&changes), None)
+            .unwrap();
+        assert!(!output.is_unchanged());
+        assert_ne!(output.document, json!({}));
+
+        // Not recorded unless delivered
+        let output = processor
+            .evaluate_with(&resp(), "user", Some(&changes), None)
+            .unwrap();
+        assert!(!output.is_unchanged());
+        changes.record(&output.changes);
+
+        let output = processor
+            .evaluate_with(&resp(), "user", Some(&changes), None)
+            .unwrap();
+        assert!(output.is_unchanged());
+        assert_eq!(output.document, json!({}));
+        assert!(output.changes.is_empty());
+
+        // Documents of other users are tracked separately
+        let output = processor
+            .evaluate_with(&resp(), "other", Some(&changes), None)
+            .unwrap();
+        assert!(!output.is_unchanged());
+
+        // Without store, always emit
+        assert!(!processor.evaluate(&resp()).unwrap().is_unchanged());
+    }
+
+    #[test]
+    fn processor_on_change_without_key() {
+        let processor = Processor::from_str(&donuts(&[(
+            "      response:\n",
+            "      emit: on-change\n\n      response:\n",
+        )]))
+        .unwrap();
+
+        let issues = processor.issues();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].rule.as_deref(), Some("Donuts"));
+        assert!(issues[0].warning);
+    }
+
+    #[test]
+    fn processor_evaluate_schema() {
+        let processor = Processor::from_str(&format!(
+            "{}\nschema:\n  required: [other]\n",
+            include_str!("donuts-processor.yaml")
+        ))
+        .unwrap();
+        let req = Request::builder()
+            .uri(Uri::from_static("http://subdomain.domain.com/donuts"))
+            .build()
+            .unwrap();
+        let resp = Response::builder()
+            .payload(include_bytes!("./donuts.json").to_vec())
+            .request(req)
+            .build()
+            .unwrap();
+
+        let output = processor.evaluate(&resp).unwrap();
+
+        assert_eq!(output.errors.len(), 1);
+    }
+
+    #[test]
+    fn processor_evaluate_foreach() {
+        let processor = Processor::from_str(&donuts(&[
+            (
+                "      path: ^/donuts$\n",
+                "      path: ^/donuts$\n      foreach: $[*]\n",
+            ),
+            (
+                "          - key: extracted.donutNames\n            value: $[*].name\n",
+                "          - key: name\n            value: $.name\n          - key: first\n            value: $root[0].id\n",
+            ),
+        ]))
+        .unwrap();
+        let req = Request::builder()
+            .uri(Uri::from_static("http://subdomain.domain.com/donuts"))
+            .build()
+            .unwrap();
+        let resp = Response::builder()
+            .payload(include_bytes!("./donuts.json").to_vec())
+            .request(req)
+            .build()
+            .unwrap();
+
+        let output = processor.evaluate(&resp).unwrap();
+
+        assert_eq!(
+            output.documents(),
+            vec![
+                json!({ "name": ["Cake"], "first": ["0001"] }),
+                json!({ "name": ["Raised"], "first": ["0001"] }),
+                json!({ "name": ["Old Fashioned"], "first": ["0001"] }),
+            ]
+        );
+    }
+
+    #[test]
+    fn processor_evaluate_output() {
+        let processor = Processor::from_str(&donuts(&[(
+            "      response:\n",
+            "      output:\n        source: shop\n        first: ${extracted.donutNames | first}\n        url: \"${flow.method} ${flow.path}\"\n\n      response:\n",
+        )]))
+        .unwrap();
+        let req = Request::builder()
+            .uri(Uri::from_static("http://subdomain.domain.com/donuts"))
+            .build()
+            .unwrap();
+        let resp = Response::builder()
+            .payload(include_bytes!("./donuts.json").to_vec())
+            .request(req)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            processor.process(&resp).unwrap(),
+            json!({ "source": "shop", "first": "Cake", "url": "GET /donuts" })
+        );
+    }
+
+    /// Definition of donuts processor with second rule writing same field.
+    fn definition_overlapping(strategy: &str) -> String {
+        donuts(&[(
+            "\ntests:",
+            &format!(
+                "    - name: Ids\n      method: GET\n      path: ^/donuts$\n      request:\n        selectors: []\n      response:\n        selectors:\n          - key: extracted.donutNames\n            value: $[*].id\n{strategy}\ntests:"
+            ),
+        )])
+    }
+
+    /// Donuts processor with second rule writing same field.
+    fn processor_overlapping(strategy: &str) -> Processor {
+        Processor::from_str(&definition_overlapping(strategy)).unwrap()
+    }
+
+    #[rstest]
+    #[case("", json!(["0001", "0002", "0003"]))]
+    #[case("            merge: append\n", json!(["Cake", "Raised", "Old Fashioned", "0001", "0002", "0003"]))]
+    #[case("            merge: keep-first\n", json!(["Cake", "Raised", "Old Fashioned"]))]
+    fn processor_process_merge(#[case] strategy: &str, #[case] expected: serde_json::Value) {
+        let processor = processor_overlapping(strategy);
+        let req = Request::builder()
+            .uri(Uri::from_static("http://subdomain.domain.com/donuts"))
+            .build()
+            .unwrap();
+        let resp = Response::builder()
+            .payload(include_bytes!("./donuts.json").to_vec())
+            .request(req)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            processor.process(&resp).unwrap(),
+            json!({ "extracted": { "donutNames": expected } })
+        );
+    }
+
+    #[test]
+    fn processor_conflicts() {
+        let processor = processor_overlapping("");
+        let issues = processor.issues();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].rule.as_deref(), Some("Ids"));
+        assert!(issues[0].warning);
+        assert!(processor.validate().is_ok());
+
+        assert!(processor_overlapping("            merge: append\n")
+            .issues()
+            .is_empty());
+    }
+
+    #[rstest]
+    #[case("spec:\n", "spec:\n  match: first\n")]
+    #[case(
+        "      path: ^/donuts$\n      request",
+        "      path: ^/ids$\n      request"
+    )]
+    #[case(
+        "    - name: Ids\n      method: GET\n",
+        "    - name: Ids\n      method: POST\n"
+    )]
+    fn processor_conflicts_disjoint(#[case] from: &str, #[case] to: &str) {
+        let processor =
+            Processor::from_str(&substitute(&definition_overlapping(""), &[(from, to)])).unwrap();
+
+        assert!(!processor
+            .issues()
+            .iter()
+            .any(|issue| issue.message.contains("conflicts")));
+    }
+
+    /// Donuts processor in `match: first` mode with catch-all fallback rule of given priority.
+    fn processor_fallback(priority: i32) -> Processor {
+        Processor::from_str(&donuts(&[
+            ("spec:\n", "spec:\n  match: first\n"),
+            (
+                "\ntests:",
+                &format!(
+                    "    - name: Fallback\n      priority: {priority}\n      method: GET\n      path: ^/\n      request:\n        selectors: []\n      response:\n        selectors:\n          - key: fallback\n            value: $[0].id\n\ntests:"
+                ),
+            ),
+        ]))
+        .unwrap()
+    }
+
+    #[rstest]
+    #[case(0, "Donuts")]
+    #[case(1, "Fallback")]
+    fn processor_evaluate_priority(#[case] priority: i32, #[case] expected: &str) {
+        let processor = processor_fallback(priority);
+        let req = Request::builder()
+            .uri(Uri::from_static("http://subdomain.domain.com/donuts"))
+            .build()
+            .unwrap();
+        let resp = Response::builder()
+            .payload(include_bytes!("./donuts.json").to_vec())
+            .request(req)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            processor.evaluate(&resp).unwrap().rules,
+            vec![expected.to_string()]
+        );
+    }
+
+    #[test]
+    fn processor_shadowed() {
+        assert!(processor_fallback(0).issues().is_empty());
+
+        let issues = processor_fallback(1).issues();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].rule.as_deref(), Some("Donuts"));
+        assert!(issues[0].warning);
+    }
+
+    #[rstest]
+    #[case("http://subdomain.domain.com/donuts/0001", "GET", true)]
+    #[case("http://subdomain.domain.com/donuts/0001", "HEAD", true)]
+    #[case("http://subdomain.domain.com/donuts/0001", "POST", false)]
+    #[case("http://subdomain.domain.com/donuts/0001/batters", "GET", false)]
+    fn processor_matches_route(
+        #[case] uri: &'static str,
+        #[case] method: &str,
+        #[case] expected: bool,
+    ) {
+        let processor = Processor::from_str(&donuts(&[
+            ("      method: GET\n", "      methods: [GET, HEAD]\n"),
+            ("      path: ^/donuts$\n", "      route: /donuts/{id}\n"),
+        ]))
+        .unwrap();
+        let req = Request::builder()
+            .method(http::Method::from_bytes(method.as_bytes()).unwrap())
+            .uri(Uri::from_static(uri))
+            .build()
+            .unwrap();
+        let resp = Response::builder().request(req).build().unwrap();
+
+        assert!(processor.validate().is_ok());
+        assert_eq!(processor.matches(&resp), expected);
+    }
+
+    #[rstest]
+    #[case("https://shop.example.com/donuts", true)]
+    #[case("https://example.com/donuts", false)]
+    #[case("http://shop.example.com/donuts", false)]
+    #[case("https://shop.example.com:8443/donuts", false)]
+    #[case("/donuts", true)] // Intercepted from TLS tunnel
+    fn processor_matches_target(#[case] uri: &'static str, #[case] expected: bool) {
+        let processor = Processor::from_str(&donuts(&[(
+            "  hostname: ^subdomain.domain.com$\n",
+            "  hostnames: [subdomain.domain.com, '*.example.com']\n  schemes: [https]\n  ports: [443]\n",
+        )]))
+        .unwrap();
+        let mut headers = http::HeaderMap::new();
+        headers.insert("host", "shop.example.com".parse().unwrap());
+        let req = Request::new(
+            http::Method::GET,
+            Uri::from_static(uri),
+            http::Version::HTTP_11,
+            kkowa_proxy_lib::http::Headers::from(headers),
+            vec![],
+        );
+        let resp = Response::builder().request(req).build().unwrap();
+
+        assert_eq!(processor.matches(&resp), expected);
+    }
+
+    #[test]
+    fn processor_validate_matchers() {
+        let processor = Processor::from_str(&donuts(&[
+            (
+                "      method: GET\n",
+                "      method: GET\n      methods: ['*']\n",
+            ),
+            ("      path: ^/donuts$\n", ""),
+        ]))
+        .unwrap();
+
+        assert_eq!(processor.issues().len(), 2);
+    }
+
+    #[rstest]
+    #[case(Strategy::Overwrite, json!([2]))]
+    #[case(Strategy::Append, json!([1, 2]))]
+    #[case(Strategy::KeepFirst, json!([1]))]
+    fn strategy_combine(#[case] strategy: Strategy, #[case] expected: serde_json::Value) {
+        assert_eq!(strategy.combine(Some(json!([1])), json!([2])), expected);
+        assert_eq!(strategy.combine(Some(json!([])), json!([2])), json!([2]));
+        assert_eq!(strategy.combine(None, json!([2])), json!([2]));
+    }
+
+    #[test]
+    fn strategy_deep_merge() {
+        assert_eq!(
+            Strategy::DeepMerge.combine(Some(json!({ "a": 1, "b": 1 })), json!({ "b": 2 })),
+            json!({ "a": 1, "b": 2 })
+        );
+        assert_eq!(
+            Strategy::Overwrite.combine(Some(json!({ "a": 1, "b": 1 })), json!({ "b": 2 })),
+            json!({ "b": 2 })
+        );
+    }
+
+    #[test]
+    fn merge_documents() {
+        let mut document = json!({ "a": { "b": 1 }, "c": 2 });
+        merge(&mut document, json!({ "a": { "d": 3 }, "c": 4 }));
+
+        assert_eq!(document, json!({ "a": { "b": 1, "d": 3 }, "c": 4 }));
+    }
+
     #[test]
     fn selector_insert() {
         let data = serde_json::from_str(include_str!("./donuts.json")).unwrap();
@@ -230,9 +1696,12 @@ fn selector_insert() {
         let selector = Selector {
             key: "extracted.donutNames".to_string(),
             value: "$[*].name".to_string(),
+            merge: None,
         };
-        selector.insert(&data, &mut document);
-
+        assert_eq!(
+            selector.insert(&data, &data, &mut document, Strategy::Overwrite),
+            Outcome::Match
+        );
         assert_eq!(
             document,
             json!({
@@ -242,4 +1711,21 @@ fn selector_insert() {
             })
         );
     }
+
+    #[test]
+    fn selector_insert_invalid() {
+        let data = serde_json::from_str(include_str!("./donuts.json")).unwrap();
+        let mut document = json!({});
+        let selector = Selector {
+            key: "extracted.donutNames".to_string(),
+            value: "$[*".to_string(),
+            merge: None,
+        };
+
+        assert_eq!(
+            selector.insert(&data, &data, &mut document, Strategy::Overwrite),
+            Outcome::Error
+        );
+        assert_eq!(document, json!({}));
+    }
 }
@@ -0,0 +1,428 @@
+//! Redaction of personal data in generated documents.
+//!
+//! Rules select fields by dot path, where `*` matches any object field or array item, or by built-in detectors of
+//! values. Selected values are dropped, masked or replaced with salted SHA-256 hash.
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use once_cell::sync::OnceCell;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+type JsonValue = serde_json::Value;
+
+/// Salt of hashes, set once on startup.
+static SALT: OnceCell<String> = OnceCell::new();
+
+lazy_static! {
+    static ref EMAIL: Regex =
+        Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}").unwrap();
+    // International number with leading `+`, or national one with parenthesized area code or 3-3/4-4 grouping, so
+    // dates, timestamps and plain numeric IDs are not taken for phone numbers
+    static ref PHONE: Regex = Regex::new(concat!(
+        r"\+\d{1,3}(?:[\s.-]?(?:\(\d{1,4}\)|\d{1,4})){2,6}",
+        r"|\(\d{2,4}\)[\s.-]?\d{3,4}[\s.-]?\d{4}\b",
+        r"|\b\d{3}[\s.-]\d{3,4}[\s.-]\d{4}\b",
+    ))
+    .unwrap();
+    static ref CARD: Regex = Regex::new(r"\b\d(?:[ -]?\d){12,18}\b").unwrap();
+}
+
+/// Set salt of hashes. Returns error if already set.
+pub fn set_salt(salt: String) -> Result<()> {
+    if SALT.set(salt).is_err() {
+        bail!("redaction salt already set");
+    }
+
+    Ok(())
+}
+
+/// Redaction rule.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Rule {
+    /// Dot path of fields to redact.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+
+    /// Detector of values to redact, anywhere in document.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    detect: Option<Detector>,
+
+    /// What to do with values.
+    action: Action,
+}
+
+/// Built-in detector of personal data.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Detector {
+    Email,
+    Phone,
+    Card,
+}
+
+/// Redaction action.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    /// Remove field.
+    Drop,
+
+    /// Replace all but last 4 characters with `*`.
+    Mask,
+
+    /// Replace with salted SHA-256 hash.
+    Hash,
+}
+
+impl Rule {
+    /// Check rule has exactly one of path and detector.
+    pub(crate) fn validate(&self) -> Result<()> {
+        match (&self.path, &self.detect) {
+            (Some(path), None) if path.is_empty() => bail!("redaction path must not be empty"),
+            (Some(_), None) | (None, Some(_)) => Ok(()),
+            _ => bail!("redaction rule must have either path or detect"),
+        }
+    }
+}
+
+/// Load global redaction policy, YAML list of rules.
+pub fn load_policy(path: &Path) -> Result<Vec<Rule>> {
+    let rules: Vec<Rule> = serde_yaml::from_str(&std::fs::read_to_string(path)?)
+        .with_context(|| format!("failed to parse redaction policy {path:?}"))?;
+    for rule in &rules {
+        rule.validate()?;
+    }
+    check_salt(&rules)?;
+
+    Ok(rules)
+}
+
+/// Check salt is set if any rule hashes values, as unsalted hashes of personal data can be reversed by dictionary.
+pub fn check_salt(rules: &[Rule]) -> Result<()> {
+    check_salt_with(rules, SALT.get().map(String::as_str))
+}
+
+fn check_salt_with(rules: &[Rule], salt: Option<&str>) -> Result<()> {
+    let hashes = rules.iter().any(|rule| matches!(rule.action, Action::Hash));
+    if hashes && salt.map_or(true, str::is_empty) {
+        bail!("redaction rules hashing values require salt, set `--redact-salt`");
+    }
+
+    Ok(())
+}
+
+/// Apply redaction rules to document in order.
+pub fn apply(rules: &[Rule], document: &mut JsonValue) {
+    for rule in rules {
+        match (&rule.path, rule.detect) {
+            (Some(path), _) => {
+                let segments: Vec<_> = path.split('.').collect();
+                redact_path(document, &segments, rule.action);
+            }
+            (None, Some(detector)) => redact_detected(document, detector, rule.action),
+            (None, None) => {}
+        }
+    }
+}
+
+fn redact_path(value: &mut JsonValue, segments: &[&str], action: Action) {
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+
+    match value {
+        JsonValue::Object(map) => {
+            if rest.is_empty() {
+                if let Action::Drop = action {
+                    match *segment {
+                        "*" => map.clear(),
+                        key => {
+                            map.remove(key);
+                        }
+                    }
+                    return;
+                }
+            }
+
+            for (key, child) in map.iter_mut() {
+                if *segment == "*" || key == *segment {
+                    match rest.is_empty() {
+                        true => *child = replace(child, action),
+                        false => redact_path(child, rest, action),
+                    }
+                }
+            }
+        }
+        JsonValue::Array(items) => {
+            let index = segment.parse::<usize>().ok();
+            if rest.is_empty() {
+                if let Action::Drop = action {
+                    match index {
+                        Some(index) if index < items.len() => {
+                            items.remove(index);
+                        }
+                        _ if *segment == "*" => items.clear(),
+                        _ => {}
+                    }
+                    return;
+                }
+            }
+
+            for (i, child) in items.iter_mut().enumerate() {
+                if *segment == "*" || index == Some(i) {
+                    match rest.is_empty() {
+                        true => *child = replace(child, action),
+                        false => redact_path(child, rest, action),
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn redact_detected(value: &mut JsonValue, detector: Detector, action: Action) {
+    match value {
+        JsonValue::Object(map) => {
+            if let Action::Drop = action {
+                map.retain(|_, child| !is_detected(child, detector));
+            }
+            for child in map.values_mut() {
+                redact_detected(child, detector, action);
+            }
+        }
+        JsonValue::Array(items) => {
+            if let Action::Drop = action {
+                items.retain(|child| !is_detected(child, detector));
+            }
+            for child in items.iter_mut() {
+                redact_detected(child, detector, action);
+            }
+        }
+        JsonValue::String(s) => {
+            let replaced = detect(detector)
+                .replace_all(s, |caps: &regex::Captures| {
+                    let found = &caps[0];
+                    match detector_matches(detector, found) {
+                        true => redacted(found, action),
+                        false => found.to_string(),
+                    }
+                })
+                .to_string();
+            *s = replaced;
+        }
+        JsonValue::Number(n) => {
+            let s = n.to_string();
+            if detect(detector).is_match(&s) && detector_matches(detector, &s) {
+                *value = JsonValue::String(redacted(&s, action));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether scalar value contains data detected.
+fn is_detected(value: &JsonValue, detector: Detector) -> bool {
+    let s = match value {
+        JsonValue::String(s) => s.clone(),
+        JsonValue::Number(n) => n.to_string(),
+        _ => return false,
+    };
+
+    detect(detector)
+        .find_iter(&s)
+        .any(|m| detector_matches(detector, m.as_str()))
+}
+
+fn detect(detector: Detector) -> &'static Regex {
+    match detector {
+        Detector::Email => &EMAIL,
+        Detector::Phone => &PHONE,
+        Detector::Card => &CARD,
+    }
+}
+
+/// Additional checks of candidate found by regular expression.
+fn detector_matches(detector: Detector, found: &str) -> bool {
+    let digits: Vec<u32> = found.chars().filter_map(|c| c.to_digit(10)).collect();
+    match detector {
+        Detector::Email => true,
+        Detector::Phone => (7..=15).contains(&digits.len()),
+        Detector::Card => (13..=19).contains(&digits.len()) && luhn(&digits),
+    }
+}
+
+fn luhn(digits: &[u32]) -> bool {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| match i % 2 {
+            1 if d * 2 > 9 => d * 2 - 9,
+            1 => d * 2,
+            _ => d,
+        })
+        .sum();
+
+    sum % 10 == 0
+}
+
+/// Replace value selected by path.
+fn replace(value: &JsonValue, action: Action) -> JsonValue {
+    let s = match value {
+        JsonValue::String(s) => s.clone(),
+        JsonValue::Null => return JsonValue::Null,
+        other => other.to_string(),
+    };
+
+    JsonValue::String(redacted(&s, action))
+}
+
+fn redacted(s: &str, action: Action) -> String {
+    match action {
+        Action::Drop => String::new(),
+        Action::Mask => {
+            let len = s.chars().count();
+            let keep = if len > 8 { 4 } else { 0 };
+            s.chars()
+                .enumerate()
+                .map(|(i, c)| if i < len - keep { '*' } else { c })
+                .collect()
+        }
+        Action::Hash => match SALT.get() {
+            Some(salt) if !salt.is_empty() => hex::encode(Sha256::digest(format!("{salt}{s}"))),
+            _ => {
+                // Never emit unsalted hashes; rules are checked on load, so only reached by offline tools
+                warn!("no redaction salt set, masking value instead of hashing");
+                redacted(s, Action::Mask)
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use serde_json::json;
+
+    use super::{apply, check_salt_with, luhn, set_salt, Rule};
+
+    fn rules(yaml: &str) -> Vec<Rule> {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn redact_path() {
+        let mut document = json!({
+            "users": [
+                { "id": "account-1234", "email": "a@example.com", "name": "A" },
+                { "id": "account-5678", "email": "b@example.com", "name": "B" },
+            ],
+        });
+        apply(
+            &rules(
+                r#"
+                - path: users.*.email
+                  action: drop
+                - path: users.*.id
+                  action: mask
+                "#,
+            ),
+            &mut document,
+        );
+
+        assert_eq!(
+            document,
+            json!({
+                "users": [
+                    { "id": "********1234", "name": "A" },
+                    { "id": "********5678", "name": "B" },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn redact_hash() {
+        let _ = set_salt("salt".to_string());
+        let mut document = json!({ "id": "account-1234" });
+        apply(&rules("[{ path: id, action: hash }]"), &mut document);
+
+        assert_eq!(document["id"].as_str().unwrap().len(), 64);
+        assert_ne!(document["id"], "account-1234");
+    }
+
+    #[test]
+    fn redact_check_salt() {
+        let hash = rules("[{ path: id, action: hash }]");
+        let mask = rules("[{ path: id, action: mask }]");
+
+        assert!(check_salt_with(&hash, None).is_err());
+        assert!(check_salt_with(&hash, Some("")).is_err());
+        assert!(check_salt_with(&hash, Some("salt")).is_ok());
+        assert!(check_salt_with(&mask, None).is_ok());
+    }
+
+    #[rstest]
+    #[case("email", "contact: a.b@example.com", "contact: ***********.com")]
+    #[case("phone", "call +1 (555) 123-4567 now", "call *************4567 now")]
+    #[case("phone", "call +44 20 7946 0958", "call ************0958")]
+    #[case("phone", "call (555) 123-4567", "call **********4567")]
+    #[case("phone", "call 555-123-4567", "call ********4567")]
+    #[case("phone", "updated 2023-01-08", "updated 2023-01-08")]
+    #[case(
+        "phone",
+        "at 2023-01-08T12:34:56+09:00",
+        "at 2023-01-08T12:34:56+09:00"
+    )]
+    #[case(
+        "phone",
+        "at 2023-01-08 12:34:56.789+0900",
+        "at 2023-01-08 12:34:56.789+0900"
+    )]
+    #[case("phone", "order 1673136000", "order 1673136000")]
+    #[case(
+        "card",
+        "paid with 4111 1111 1111 1111",
+        "paid with ***************1111"
+    )]
+    #[case("card", "order 1234567890123", "order 1234567890123")] // Fails Luhn check
+    fn redact_detected(#[case] detector: &str, #[case] value: &str, #[case] expected: &str) {
+        let mut document = json!({ "nested": [{ "value": value }] });
+        apply(
+            &rules(&format!("[{{ detect: {detector}, action: mask }}]")),
+            &mut document,
+        );
+
+        assert_eq!(document, json!({ "nested": [{ "value": expected }] }));
+    }
+
+    #[test]
+    fn redact_detected_drop() {
+        let mut document = json!({ "email": "a@example.com", "name": "A" });
+        apply(&rules("[{ detect: email, action: drop }]"), &mut document);
+
+        assert_eq!(document, json!({ "name": "A" }));
+    }
+
+    #[test]
+    fn luhn_check() {
+        assert!(luhn(&[4, 2, 4, 2, 4, 2, 4, 2, 4, 2, 4, 2, 4, 2, 4, 2]));
+        assert!(!luhn(&[1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn rule_validate() {
+        assert!(rules("[{ path: id, action: drop }]")[0].validate().is_ok());
+        assert!(rules("[{ action: drop }]")[0].validate().is_err());
+        assert!(rules("[{ path: id, detect: email, action: drop }]")[0]
+            .validate()
+            .is_err());
+    }
+}
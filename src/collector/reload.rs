@@ -0,0 +1,172 @@
+//! Hot reloading of processor definitions.
+
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use arc_swap::ArcSwap;
+use notify::{RecursiveMode, Watcher};
+use tokio::{signal::unix::{signal, SignalKind},
+            sync::mpsc};
+use tracing::{debug, error, info};
+
+use super::{bundle::TrustedKeys, loader, redact, Processor};
+
+/// Delay to wait for more file system events before reloading, as editors usually emit several events per save.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Atomically swappable handle to set of processors, shared between collector and reloader.
+#[derive(Clone, Debug, Default)]
+pub struct Processors {
+    inner: Arc<ArcSwap<Vec<Processor>>>,
+}
+
+impl Processors {
+    /// Create new handle holding given processors.
+    pub fn new(processors: Vec<Processor>) -> Self {
+        Self {
+            inner: Arc::new(ArcSwap::from_pointee(processors)),
+        }
+    }
+
+    /// Get snapshot of current processors. Snapshot is not affected by swaps happening after.
+    pub fn load(&self) -> Arc<Vec<Processor>> {
+        self.inner.load_full()
+    }
+
+    /// Validate given processors and replace current set with them. If validation fails, current set is kept.
+    pub fn swap(&self, processors: Vec<Processor>) -> Result<()> {
+        for processor in &processors {
+            processor.validate()?;
+            redact::check_salt(processor.redaction())?;
+        }
+        self.inner.store(Arc::new(processors));
+
+        Ok(())
+    }
+}
+
+impl From<Vec<Processor>> for Processors {
+    fn from(processors: Vec<Processor>) -> Self {
+        Self::new(processors)
+    }
+}
+
+/// Reloads processors on file system changes of processor path or on SIGHUP.
+#[derive(Debug)]
+pub struct Reloader {
+    /// File or directory path for processor definition file(s).
+    path: PathBuf,
+
+    /// Handle to swap processors of.
+    processors: Processors,
+
+    /// Keys trusted to sign processor bundles.
+    keys: TrustedKeys,
+}
+
+impl Reloader {
+    /// Create new reloader.
+    pub fn new(path: PathBuf, processors: Processors, keys: TrustedKeys) -> Self {
+        Self {
+            path,
+            processors,
+            keys,
+        }
+    }
+
+    /// Reload processors from path. Current processors are kept if any of definitions fails to load or validate.
+    pub fn reload(&self) -> Result<()> {
+        let processors = loader::load(Some(&self.path), &self.keys)?;
+        let count = processors.len();
+        self.processors.swap(processors)?;
+        info!(
+            "reloaded {count} processor(s) from {path:?}",
+            path = self.path
+        );
+
+        Ok(())
+    }
+
+    /// Watch for changes and reload until signal streams close.
+    pub async fn run(self) -> Result<()> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+                Ok(event) if !event.kind.is_access() => {
+                    let _ = tx.send(());
+                }
+                Ok(_) => {}
+                Err(err) => error!("processor watch error: {err}"),
+            })
+            .context("failed to create file system watcher")?;
+
+        // Watch parent directory for single file, as editors often replace file on save
+        let watch_path = match self.path.is_dir() {
+            true => self.path.as_path(),
+            false => self.path.parent().unwrap_or(&self.path),
+        };
+        watcher
+            .watch(watch_path, RecursiveMode::NonRecursive)
+            .with_context(|| format!("failed to watch {watch_path:?}"))?;
+
+        let mut hangup =
+            signal(SignalKind::hangup()).context("failed to install SIGHUP handler")?;
+
+        loop {
+            tokio::select! {
+                Some(()) = rx.recv() => {
+                    tokio::time::sleep(DEBOUNCE).await;
+                    while rx.try_recv().is_ok() {}
+                    debug!("processor path {path:?} changed", path = self.path);
+                }
+                Some(()) = hangup.recv() => {
+                    debug!("received SIGHUP");
+                }
+                else => break,
+            }
+
+            if let Err(err) = self.reload() {
+                error!("failed to reload processors, keeping current ones: {err:#}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::Processors;
+    use crate::collector::{processor::tests::donuts, Processor};
+
+    #[test]
+    fn processors_swap() {
+        let processors = Processors::default();
+        assert!(processors.load().is_empty());
+
+        let snapshot = processors.load();
+        processors
+            .swap(vec![Processor::from_str(include_str!(
+                "donuts-processor.yaml"
+            ))
+            .unwrap()])
+            .unwrap();
+
+        assert_eq!(processors.load().len(), 1);
+        assert!(snapshot.is_empty());
+    }
+
+    #[test]
+    fn processors_swap_invalid() {
+        let processors = Processors::new(vec![Processor::from_str(include_str!(
+            "donuts-processor.yaml"
+        ))
+        .unwrap()]);
+        let invalid = Processor::from_str(&donuts(&[("$[*].name", "$[*")])).unwrap();
+
+        assert!(processors.swap(vec![invalid]).is_err());
+        assert_eq!(processors.load().len(), 1);
+    }
+}
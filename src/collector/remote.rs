@@ -0,0 +1,275 @@
+//! Fetching processor bundles from core server.
+
+use std::{fs,
+          path::{Path, PathBuf},
+          time::Duration};
+
+use anyhow::{Context, Result};
+use reqwest::{header::{ETAG, IF_NONE_MATCH},
+              StatusCode};
+use tracing::{debug, info, warn};
+
+use super::{bundle::{Bundle, TrustedKeys},
+            Processor, Processors};
+
+/// Polls signed processor bundle endpoint using ETag and caches last good bundle to disk.
+#[derive(Debug)]
+pub struct Fetcher {
+    /// Bundle endpoint URL.
+    url: String,
+
+    /// File path to cache last good bundle.
+    cache: Option<PathBuf>,
+
+    /// Interval between polls.
+    interval: Duration,
+
+    /// ETag of current bundle.
+    etag: Option<String>,
+
+    /// Keys trusted to sign bundles.
+    keys: TrustedKeys,
+
+    client: reqwest::Client,
+}
+
+impl Fetcher {
+    /// Create new fetcher polling given URL every minute.
+    pub fn new(url: String, keys: TrustedKeys) -> Self {
+        Self {
+            url,
+            cache: None,
+            interval: Duration::from_secs(60),
+            etag: None,
+            keys,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Set file path to cache last good bundle.
+    pub fn cache(mut self, path: PathBuf) -> Self {
+        self.cache = Some(path);
+        self
+    }
+
+    /// Set interval between polls.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Load last good bundle from cache, restoring its ETag too. Returns `None` if there is no cache.
+    pub fn load_cache(&mut self) -> Result<Option<Vec<Processor>>> {
+        let path = match &self.cache {
+            Some(path) if path.exists() => path,
+            _ => return Ok(None),
+        };
+        let bytes =
+            fs::read(path).with_context(|| format!("failed to read bundle cache {path:?}"))?;
+        let processors = Bundle::parse(&bytes)?.verify(&self.keys)?;
+        self.etag = fs::read_to_string(etag_path(path)).ok();
+
+        Ok(Some(processors))
+    }
+
+    /// Fetch bundle from server. Returns `None` if bundle not modified since last fetch.
+    async fn fetch(&self) -> Result<Option<Fetched>> {
+        let mut req = self.client.get(&self.url);
+        if let Some(etag) = &self.etag {
+            req = req.header(IF_NONE_MATCH, etag);
+        }
+
+        let resp = req.send().await?;
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+
+        let resp = resp.error_for_status()?;
+        let etag = resp
+            .headers()
+            .get(ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let bytes = resp.bytes().await?.to_vec();
+        let processors = Bundle::parse(&bytes)?.verify(&self.keys)?;
+
+        Ok(Some(Fetched {
+            processors,
+            bytes,
+            etag,
+        }))
+    }
+
+    /// Fetch bundle from server and swap processors with it. Bundle is cached, and its ETag kept, only once swap
+    /// accepted it. Returns number of processors swapped in, or `None` if bundle not modified since last fetch.
+    pub async fn update(&mut self, processors: &Processors) -> Result<Option<usize>> {
+        let fetched = match self.fetch().await? {
+            Some(fetched) => fetched,
+            None => return Ok(None),
+        };
+
+        let count = fetched.processors.len();
+        processors
+            .swap(fetched.processors)
+            .context("fetched processors are invalid")?;
+        if let Some(path) = &self.cache {
+            if let Err(err) = write_cache(path, &fetched.bytes, fetched.etag.as_deref()) {
+                warn!("failed to write bundle cache {path:?}: {err:#}");
+            }
+        }
+        self.etag = fetched.etag;
+
+        Ok(Some(count))
+    }
+
+    /// Poll bundle endpoint forever, swapping processors whenever new bundle fetched.
+    pub async fn run(mut self, processors: Processors) {
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            interval.tick().await;
+            match self.update(&processors).await {
+                Ok(Some(count)) => info!("fetched {count} processor(s) from {url}", url = self.url),
+                Ok(None) => debug!("processor bundle not modified"),
+                Err(err) => {
+                    warn!("failed to update processors from bundle, keeping current ones: {err:#}")
+                }
+            }
+        }
+    }
+}
+
+/// Verified bundle fetched from server.
+struct Fetched {
+    processors: Vec<Processor>,
+
+    /// Raw bundle, to cache once accepted.
+    bytes: Vec<u8>,
+
+    etag: Option<String>,
+}
+
+fn etag_path(path: &Path) -> PathBuf {
+    let mut p = path.as_os_str().to_owned();
+    p.push(".etag");
+
+    p.into()
+}
+
+/// Write cache to temporary file first then rename, so interrupted write won't corrupt last good bundle.
+fn write_cache(path: &Path, bytes: &[u8], etag: Option<&str>) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, bytes)?;
+    fs::rename(&tmp, path)?;
+
+    match etag {
+        Some(etag) => fs::write(etag_path(path), etag)?,
+        None => {
+            let _ = fs::remove_file(etag_path(path));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::prelude::*;
+    use rstest::*;
+
+    use super::Fetcher;
+    use crate::collector::{bundle::{pack, tests::keypair, TrustedKeys},
+                           processor::tests::donuts,
+                           Processors};
+
+    #[fixture]
+    fn keys() -> TrustedKeys {
+        TrustedKeys::from(vec![keypair(1).public])
+    }
+
+    fn bundle(seed: u8) -> Vec<u8> {
+        let files = [
+            ("a.yaml", include_bytes!("donuts-processor.yaml").as_slice()),
+            ("b.yaml", include_bytes!("donuts-processor.yaml").as_slice()),
+        ];
+
+        pack(&files, &keypair(seed)).unwrap()
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn fetcher_fetch(keys: TrustedKeys) {
+        let server = MockServer::start();
+        let cache = tempfile::tempdir().unwrap();
+        let cache_path = cache.path().join("processors.bundle");
+        let mut fetcher =
+            Fetcher::new(server.url("/bundle"), keys.clone()).cache(cache_path.clone());
+
+        let processors = Processors::new(vec![]);
+
+        let mut mock = server.mock(|when, then| {
+            when.method(GET).path("/bundle");
+            then.status(200).header("ETag", "\"v1\"").body(bundle(1));
+        });
+        assert_eq!(fetcher.update(&processors).await.unwrap(), Some(2));
+        assert_eq!(processors.load().len(), 2);
+        mock.assert();
+        mock.delete();
+
+        // Not modified
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/bundle")
+                .header("If-None-Match", "\"v1\"");
+            then.status(304);
+        });
+        assert_eq!(fetcher.update(&processors).await.unwrap(), None);
+        mock.assert();
+
+        // Offline start from cache
+        let mut fetcher = Fetcher::new(server.url("/bundle"), keys).cache(cache_path);
+        assert_eq!(fetcher.load_cache().unwrap().unwrap().len(), 2);
+        assert_eq!(fetcher.etag.as_deref(), Some("\"v1\""));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn fetcher_fetch_untrusted(keys: TrustedKeys) {
+        let server = MockServer::start();
+        let cache = tempfile::tempdir().unwrap();
+        let cache_path = cache.path().join("processors.bundle");
+        let mut fetcher = Fetcher::new(server.url("/bundle"), keys).cache(cache_path.clone());
+
+        server.mock(|when, then| {
+            when.method(GET).path("/bundle");
+            then.status(200).header("ETag", "\"v2\"").body(bundle(2));
+        });
+        assert!(fetcher.update(&Processors::new(vec![])).await.is_err());
+        assert!(fetcher.etag.is_none());
+        assert!(!cache_path.exists());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn fetcher_update_invalid(keys: TrustedKeys) {
+        let server = MockServer::start();
+        let cache = tempfile::tempdir().unwrap();
+        let cache_path = cache.path().join("processors.bundle");
+        let mut fetcher = Fetcher::new(server.url("/bundle"), keys).cache(cache_path.clone());
+        let processors = Processors::new(vec![]);
+
+        // Signed by trusted key, but rejected on swap
+        let invalid = donuts(&[("$[*].name", "$[*")]);
+        let files = [("a.yaml", invalid.as_bytes())];
+        server.mock(|when, then| {
+            when.method(GET).path("/bundle");
+            then.status(200)
+                .header("ETag", "\"v3\"")
+                .body(pack(&files, &keypair(1)).unwrap());
+        });
+        assert!(fetcher.update(&processors).await.is_err());
+        assert!(processors.load().is_empty());
+        assert!(fetcher.etag.is_none());
+        assert!(!cache_path.exists());
+    }
+}
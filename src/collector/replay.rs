@@ -0,0 +1,146 @@
+//! Offline replay of processors against HTTP archives.
+
+use std::{collections::BTreeMap, io::Write};
+
+use anyhow::Result;
+use serde_json::json;
+use tracing::warn;
+
+use super::{redact, Processor};
+use crate::har::Har;
+
+/// Summary of replay.
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Number of entries replayed.
+    pub entries: usize,
+
+    /// Number of entries failed to convert as flow.
+    pub errors: usize,
+
+    /// Number of documents written.
+    pub documents: usize,
+
+    /// Number of matches keyed by processor name and rule label.
+    pub matches: BTreeMap<(String, String), usize>,
+}
+
+/// Run processors against each entry of HTTP archive, writing generated documents as NDJSON. Global redaction rules
+/// are applied to documents as collector does.
+pub fn replay<W>(
+    har: &Har,
+    processors: &[Processor],
+    redact: &[redact::Rule],
+    out: &mut W,
+) -> Result<Summary>
+where
+    W: Write,
+{
+    let mut summary = Summary::default();
+    for (index, entry) in har.log.entries.iter().enumerate() {
+        summary.entries += 1;
+        let resp = match entry.to_response() {
+            Ok(resp) => resp,
+            Err(err) => {
+                warn!("skipping entry #{index}: {err:#}");
+                summary.errors += 1;
+                continue;
+            }
+        };
+
+        for processor in processors {
+            let output = match processor.evaluate(&resp) {
+                Some(output) if !output.rules.is_empty() => output,
+                _ => continue,
+            };
+
+            for mut document in output.documents() {
+                redact::apply(redact, &mut document);
+                let line = json!({
+                    "processor": processor.name(),
+                    "entry": index,
+                    "url": entry.request.url,
+                    "document": document,
+                });
+                writeln!(out, "{line}")?;
+                summary.documents += 1;
+            }
+
+            for rule in output.rules {
+                *summary
+                    .matches
+                    .entry((processor.name().to_string(), rule))
+                    .or_default() += 1;
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use serde_json::json;
+
+    use super::replay;
+    use crate::{collector::{redact, Processor},
+                har::Har};
+
+    #[test]
+    fn replay_har() {
+        let har = Har::from_str(include_str!("donuts.har")).unwrap();
+        let processors = vec![Processor::from_str(include_str!("donuts-processor.yaml")).unwrap()];
+        let mut out = Vec::new();
+
+        let summary = replay(&har, &processors, &[], &mut out).unwrap();
+
+        assert_eq!(summary.entries, 2);
+        assert_eq!(summary.documents, 1);
+        assert_eq!(
+            summary
+                .matches
+                .get(&("Name".to_string(), "Donuts".to_string())),
+            Some(&1)
+        );
+
+        let lines: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![json!({
+                "processor": "Name",
+                "entry": 0,
+                "url": "http://subdomain.domain.com/donuts",
+                "document": {
+                    "extracted": {
+                        "donutNames": ["Cake", "Raised", "Old Fashioned"]
+                    }
+                }
+            })]
+        );
+    }
+
+    #[test]
+    fn replay_har_redact() {
+        let har = Har::from_str(include_str!("donuts.har")).unwrap();
+        let processors = vec![Processor::from_str(include_str!("donuts-processor.yaml")).unwrap()];
+        let redact = serde_yaml::from_str::<Vec<redact::Rule>>(
+            "[{ path: extracted.donutNames.*, action: mask }]",
+        )
+        .unwrap();
+        let mut out = Vec::new();
+
+        replay(&har, &processors, &redact, &mut out).unwrap();
+
+        let line: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            line["document"]["extracted"]["donutNames"],
+            json!(["****", "******", "*********oned"])
+        );
+    }
+}
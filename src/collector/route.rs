@@ -0,0 +1,186 @@
+//! Route templates and method matchers of rules.
+
+use std::fmt;
+
+use anyhow::{bail, Result};
+use http::Method;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+lazy_static! {
+    static ref PARAMETER: Regex = Regex::new(r"\{([^{}]*)\}").unwrap();
+    static ref NAME: Regex = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*\*?$").unwrap();
+}
+
+/// Route template of path such as `/api/{version}/items/{id}`, compiled to regular expression with named groups.
+///
+/// Parameter `{name}` matches single path segment, and `{name*}` matches rest of path including slashes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Route {
+    template: String,
+    regex: Regex,
+}
+
+impl TryFrom<String> for Route {
+    type Error = anyhow::Error;
+
+    fn try_from(template: String) -> Result<Self> {
+        if !template.starts_with('/') {
+            bail!("route `{template}` must start with `/`");
+        }
+
+        let mut pattern = String::from("^");
+        let mut last = 0;
+        for caps in PARAMETER.captures_iter(&template) {
+            let (whole, name) = (caps.get(0).unwrap(), &caps[1]);
+            if !NAME.is_match(name) {
+                bail!("invalid parameter `{{{name}}}` in route `{template}`");
+            }
+
+            pattern.push_str(&literal(&template[last..whole.start()], &template)?);
+            match name.strip_suffix('*') {
+                Some(name) => pattern.push_str(&format!("(?P<{name}>.*)")),
+                None => pattern.push_str(&format!("(?P<{name}>[^/]+)")),
+            }
+            last = whole.end();
+        }
+        pattern.push_str(&literal(&template[last..], &template)?);
+        pattern.push('$');
+
+        let regex = Regex::new(&pattern)
+            .map_err(|err| anyhow::anyhow!("invalid route `{template}`: {err}"))?;
+
+        Ok(Self { template, regex })
+    }
+}
+
+impl From<Route> for String {
+    fn from(route: Route) -> Self {
+        route.template
+    }
+}
+
+impl Route {
+    /// Template of route as written.
+    pub fn as_str(&self) -> &str {
+        &self.template
+    }
+
+    /// Regular expression compiled from template.
+    pub fn regex(&self) -> &Regex {
+        &self.regex
+    }
+}
+
+/// Escape literal part of route, rejecting stray braces.
+fn literal(part: &str, template: &str) -> Result<String> {
+    if part.contains(['{', '}']) {
+        bail!("unbalanced braces in route `{template}`");
+    }
+
+    Ok(regex::escape(part))
+}
+
+/// Request method matcher, where `*` matches any method.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum MethodMatcher {
+    Any,
+    Method(Method),
+}
+
+impl TryFrom<String> for MethodMatcher {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        match s.as_str() {
+            "*" => Ok(Self::Any),
+            method => Ok(Self::Method(Method::from_bytes(method.as_bytes())?)),
+        }
+    }
+}
+
+impl From<MethodMatcher> for String {
+    fn from(matcher: MethodMatcher) -> Self {
+        matcher.to_string()
+    }
+}
+
+impl fmt::Display for MethodMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Any => write!(f, "*"),
+            Self::Method(method) => write!(f, "{method}"),
+        }
+    }
+}
+
+impl MethodMatcher {
+    /// Check method matches.
+    pub fn matches(&self, method: &Method) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Method(expected) => expected == method,
+        }
+    }
+
+    /// Check all methods other matcher matches are matched by this one too.
+    pub fn covers(&self, other: &MethodMatcher) -> bool {
+        match (self, other) {
+            (Self::Any, _) => true,
+            (Self::Method(_), Self::Any) => false,
+            (Self::Method(a), Self::Method(b)) => a == b,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+    use rstest::*;
+
+    use super::{MethodMatcher, Route};
+
+    #[rstest]
+    #[case("/api/{version}/items/{id}", "/api/v2/items/p-1", true)]
+    #[case("/api/{version}/items/{id}", "/api/v2/items/p-1/reviews", false)]
+    #[case("/files/{path*}", "/files/a/b.txt", true)]
+    #[case("/search.json", "/searchxjson", false)]
+    fn route_matches(#[case] template: &str, #[case] path: &str, #[case] expected: bool) {
+        let route = Route::try_from(template.to_string()).unwrap();
+
+        assert_eq!(route.regex().is_match(path), expected);
+    }
+
+    #[test]
+    fn route_parameters() {
+        let route = Route::try_from("/api/{version}/items/{id}".to_string()).unwrap();
+        let caps = route.regex().captures("/api/v2/items/p-1").unwrap();
+
+        assert_eq!(&caps["version"], "v2");
+        assert_eq!(&caps["id"], "p-1");
+    }
+
+    #[rstest]
+    #[case("api/{id}")]
+    #[case("/api/{id")]
+    #[case("/api/{1d}")]
+    #[case("/api/{id}/{id}")]
+    fn route_invalid(#[case] template: &str) {
+        assert!(Route::try_from(template.to_string()).is_err());
+    }
+
+    #[test]
+    fn method_matcher() {
+        let any: MethodMatcher = serde_yaml::from_str("'*'").unwrap();
+        let get: MethodMatcher = serde_yaml::from_str("GET").unwrap();
+
+        assert!(any.matches(&Method::POST));
+        assert!(get.matches(&Method::GET));
+        assert!(!get.matches(&Method::HEAD));
+        assert!(any.covers(&get));
+        assert!(!get.covers(&any));
+    }
+}
@@ -0,0 +1,215 @@
+//! Output JSON Schema of processors, and dead-letter sink of documents not conforming to it.
+
+use std::{fmt,
+          fs::{File, OpenOptions},
+          io::Write,
+          path::PathBuf,
+          sync::{Arc, Mutex}};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use jsonschema::JSONSchema;
+use metrics::increment_counter;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+type JsonValue = serde_json::Value;
+
+/// Schema given as file path relative to processor definition, or inline.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Source {
+    Path(String),
+    Inline(JsonValue),
+}
+
+/// Output JSON Schema of processor. Schema files are compiled when processor definition loaded from file or bundle.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "Source", into = "Source")]
+pub struct OutputSchema {
+    source: Source,
+    compiled: Option<Arc<JSONSchema>>,
+}
+
+impl TryFrom<Source> for OutputSchema {
+    type Error = anyhow::Error;
+
+    fn try_from(source: Source) -> Result<Self> {
+        let compiled = match &source {
+            Source::Inline(schema) => Some(Arc::new(compile(schema)?)),
+            Source::Path(_) => None,
+        };
+
+        Ok(Self { source, compiled })
+    }
+}
+
+impl From<OutputSchema> for Source {
+    fn from(schema: OutputSchema) -> Self {
+        schema.source
+    }
+}
+
+impl fmt::Debug for OutputSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OutputSchema")
+            .field("source", &self.source)
+            .field("compiled", &self.compiled.is_some())
+            .finish()
+    }
+}
+
+impl OutputSchema {
+    /// Read and compile schema file with given reader, taking path of schema file. Does nothing for inline schemas.
+    pub(crate) fn resolve<F>(&mut self, read: F) -> Result<()>
+    where
+        F: FnOnce(&str) -> Result<Vec<u8>>,
+    {
+        let path = match (&self.source, &self.compiled) {
+            (Source::Path(path), None) => path,
+            _ => return Ok(()),
+        };
+
+        let schema: JsonValue = serde_json::from_slice(&read(path)?)
+            .with_context(|| format!("failed to parse output schema {path}"))?;
+        self.compiled = Some(Arc::new(
+            compile(&schema).with_context(|| format!("invalid output schema {path}"))?,
+        ));
+
+        Ok(())
+    }
+
+    /// Validate document against schema, returning errors found. If schema not compiled yet, nothing is checked.
+    pub(crate) fn validate(&self, document: &JsonValue) -> Vec<String> {
+        let compiled = match &self.compiled {
+            Some(compiled) => compiled,
+            None => return vec![],
+        };
+
+        match compiled.validate(document) {
+            Ok(()) => vec![],
+            Err(errors) => errors
+                .map(|err| format!("{path}: {err}", path = err.instance_path))
+                .collect(),
+        }
+    }
+}
+
+fn compile(schema: &JsonValue) -> Result<JSONSchema> {
+    JSONSchema::compile(schema).map_err(|err| anyhow!("invalid JSON Schema: {err}"))
+}
+
+/// Sink of documents failed schema validation, appending them with errors to NDJSON file.
+///
+/// Each document sent is counted to Prometheus counter `documents_invalid_total`.
+#[derive(Clone, Debug)]
+pub struct DeadLetter {
+    file: Arc<Mutex<File>>,
+}
+
+impl DeadLetter {
+    /// Open dead-letter file, creating it if not exists.
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("failed to open dead-letter file {path:?}"))?;
+
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// Append document with its validation errors.
+    pub fn send(
+        &self,
+        processor: &str,
+        url: &str,
+        document: &JsonValue,
+        errors: &[String],
+    ) -> Result<()> {
+        increment_counter!("documents_invalid_total", "processor" => processor.to_string());
+
+        let line = json!({
+            "time": Utc::now().to_rfc3339(),
+            "processor": processor,
+            "url": url,
+            "document": document,
+            "errors": errors,
+        });
+        writeln!(self.file.lock().unwrap(), "{line}")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{DeadLetter, OutputSchema};
+
+    fn schema() -> OutputSchema {
+        serde_yaml::from_str(
+            r#"
+            type: object
+            required: [name]
+            properties:
+              name:
+                type: string
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn schema_validate() {
+        let schema = schema();
+
+        assert!(schema.validate(&json!({ "name": "Cake" })).is_empty());
+        assert_eq!(
+            schema.validate(&json!({ "name": 1 })),
+            vec![r#"/name: 1 is not of type "string""#.to_string()]
+        );
+    }
+
+    #[test]
+    fn schema_resolve() {
+        let mut schema: OutputSchema = serde_yaml::from_str("schema.json").unwrap();
+        assert!(schema.validate(&json!({})).is_empty());
+
+        schema
+            .resolve(|path| {
+                assert_eq!(path, "schema.json");
+                Ok(br#"{ "required": ["name"] }"#.to_vec())
+            })
+            .unwrap();
+        assert_eq!(schema.validate(&json!({})).len(), 1);
+    }
+
+    #[test]
+    fn schema_invalid() {
+        assert!(serde_yaml::from_str::<OutputSchema>("{ type: 1 }").is_err());
+    }
+
+    #[test]
+    fn dead_letter_send() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dead-letter.ndjson");
+        let dead_letter = DeadLetter::open(path.clone()).unwrap();
+
+        for _ in 0..2 {
+            dead_letter
+                .send(
+                    "Name",
+                    "http://domain.com/",
+                    &json!({}),
+                    &["error".to_string()],
+                )
+                .unwrap();
+        }
+
+        assert_eq!(std::fs::read_to_string(path).unwrap().lines().count(), 2);
+    }
+}
@@ -0,0 +1,235 @@
+//! Authoring assistant suggesting selectors for sample JSON bodies.
+
+use std::collections::BTreeSet;
+
+use anyhow::{anyhow, Result};
+use http::Method;
+use serde_json::json;
+
+use super::{version, Processor};
+
+type JsonValue = serde_json::Value;
+
+/// Candidate JsonPath expression of selector.
+#[derive(Debug, PartialEq)]
+pub struct Suggestion {
+    /// JsonPath expression.
+    pub path: String,
+
+    /// Values selected by expression from sample.
+    pub matches: Vec<JsonValue>,
+}
+
+impl Suggestion {
+    /// Dot path key derived from field names of expression, for use as selector key.
+    pub fn key(&self) -> String {
+        let fields: Vec<_> = self
+            .path
+            .split(|c| c == '.' || c == '[')
+            .filter_map(|segment| {
+                let segment = segment.trim_end_matches(']');
+                let segment = segment.trim_matches('\'');
+                match segment {
+                    "$" | "*" | "" => None,
+                    _ if segment.parse::<usize>().is_ok() => None,
+                    _ => Some(segment.replace(' ', "_")),
+                }
+            })
+            .collect();
+
+        match fields.is_empty() {
+            true => "value".to_string(),
+            false => fields.join("."),
+        }
+    }
+}
+
+/// Suggest JsonPath expressions selecting scalar values of body. Array items are generalized with wildcard.
+///
+/// If `find` is set, only expressions selecting value equal to it are suggested, including exact location of value.
+pub fn suggest(body: &JsonValue, find: Option<&str>) -> Result<Vec<Suggestion>> {
+    let mut paths = BTreeSet::new();
+    walk(body, "$".to_string(), "$".to_string(), find, &mut paths);
+
+    let mut suggestions = Vec::with_capacity(paths.len());
+    for path in paths {
+        let matches: Vec<JsonValue> = jsonpath_lib::select(body, &path)
+            .map_err(|err| anyhow!("invalid JsonPath `{path}`: {err:?}"))?
+            .into_iter()
+            .cloned()
+            .collect();
+        if let Some(find) = find {
+            if !matches.iter().any(|value| equals(value, find)) {
+                continue;
+            }
+        }
+
+        suggestions.push(Suggestion { path, matches });
+    }
+
+    Ok(suggestions)
+}
+
+/// Collect paths of scalar values, both generalized and, if looking for value, exact ones.
+fn walk(
+    value: &JsonValue,
+    general: String,
+    exact: String,
+    find: Option<&str>,
+    paths: &mut BTreeSet<String>,
+) {
+    match value {
+        JsonValue::Object(map) => {
+            for (key, value) in map {
+                let field = field(key);
+                walk(
+                    value,
+                    format!("{general}{field}"),
+                    format!("{exact}{field}"),
+                    find,
+                    paths,
+                );
+            }
+        }
+        JsonValue::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                walk(
+                    item,
+                    format!("{general}[*]"),
+                    format!("{exact}[{index}]"),
+                    find,
+                    paths,
+                );
+            }
+        }
+        scalar => {
+            if let Some(find) = find {
+                if equals(scalar, find) {
+                    paths.insert(exact);
+                }
+            }
+            paths.insert(general);
+        }
+    }
+}
+
+/// Field accessor of key, using bracket notation if key is not plain identifier.
+fn field(key: &str) -> String {
+    let plain = !key.is_empty()
+        && !key.starts_with(|c: char| c.is_ascii_digit())
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match plain {
+        true => format!(".{key}"),
+        false => format!("['{}']", key.replace('\'', "\\'")),
+    }
+}
+
+fn equals(value: &JsonValue, find: &str) -> bool {
+    match value {
+        JsonValue::String(s) => s == find,
+        other => other.to_string() == find,
+    }
+}
+
+/// Generate skeleton processor definition in YAML, with single rule selecting suggested values from response.
+pub fn skeleton(
+    name: &str,
+    host: &str,
+    method: &Method,
+    path: &str,
+    suggestions: &[Suggestion],
+) -> Result<String> {
+    let mut keys = BTreeSet::new();
+    let selectors: Vec<JsonValue> = suggestions
+        .iter()
+        .map(|suggestion| {
+            let base = format!("extracted.{key}", key = suggestion.key());
+            let mut key = base.clone();
+            let mut n = 2;
+            while !keys.insert(key.clone()) {
+                key = format!("{base}{n}");
+                n += 1;
+            }
+
+            json!({ "key": key, "value": suggestion.path })
+        })
+        .collect();
+
+    let processor: Processor = serde_json::from_value(json!({
+        "apiVersion": version::API_VERSION,
+        "kind": version::KIND,
+        "metadata": {
+            "name": name,
+            "hostname": format!("^{}$", regex::escape(host)),
+        },
+        "spec": {
+            "rules": [{
+                "name": name,
+                "description": format!("Extract fields of {method} {path}."),
+                "method": method.as_str(),
+                "path": format!("^{}$", regex::escape(path)),
+                "request": { "selectors": [] },
+                "response": { "selectors": selectors },
+            }],
+        },
+    }))?;
+    processor.validate()?;
+
+    Ok(serde_yaml::to_string(&processor)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use http::Method;
+    use serde_json::json;
+
+    use super::{skeleton, suggest};
+    use crate::collector::Processor;
+
+    #[test]
+    fn suggest_paths() {
+        let body = serde_json::from_str(include_str!("donuts.json")).unwrap();
+        let suggestions = suggest(&body, None).unwrap();
+
+        let name = suggestions.iter().find(|s| s.path == "$[*].name").unwrap();
+        assert_eq!(
+            name.matches,
+            vec![json!("Cake"), json!("Raised"), json!("Old Fashioned")]
+        );
+        assert_eq!(name.key(), "name");
+        assert!(suggestions
+            .iter()
+            .any(|s| s.path == "$[*].batters.batter[*].type"));
+    }
+
+    #[test]
+    fn suggest_find() {
+        let body = json!({ "data": { "user name": "lasuillard", "id": 1 } });
+        let suggestions = suggest(&body, Some("lasuillard")).unwrap();
+
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].path, "$.data['user name']");
+        assert_eq!(suggestions[0].key(), "data.user_name");
+    }
+
+    #[test]
+    fn skeleton_processor() {
+        let body = serde_json::from_str(include_str!("donuts.json")).unwrap();
+        let suggestions = suggest(&body, Some("Raised")).unwrap();
+        let yaml = skeleton(
+            "Donuts",
+            "subdomain.domain.com",
+            &Method::GET,
+            "/donuts",
+            &suggestions,
+        )
+        .unwrap();
+
+        let processor = Processor::from_str(&yaml).unwrap();
+        assert!(processor.matches_host("subdomain.domain.com"));
+        assert!(yaml.contains("$[*].name"));
+        assert!(yaml.contains("$[1].name"));
+    }
+}
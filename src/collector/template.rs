@@ -0,0 +1,253 @@
+//! Output templates shaping documents generated by rules.
+//!
+//! Template is any JSON value where strings may contain placeholders `${reference}`, optionally with filter as
+//! `${reference | first}`. References are resolved as:
+//!
+//! - `flow.method`, `flow.url`, `flow.host`, `flow.path`, `flow.status`: metadata of flow.
+//! - `capture.<N>` or `capture.<name>`: capture groups of rule's path regular expression.
+//! - Anything else: dot path of selector results.
+//!
+//! String consisting of single placeholder is replaced with referenced value as is; otherwise placeholders are
+//! interpolated as strings. Values not found are rendered as `null`, or empty string when interpolated.
+
+use anyhow::{bail, Result};
+use json_dotpath::DotPaths;
+use kkowa_proxy_lib::http::Response;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map};
+
+use super::host::Target;
+
+type JsonValue = serde_json::Value;
+
+lazy_static! {
+    static ref PLACEHOLDER: Regex =
+        Regex::new(r"\$\{\s*([^}|\s]+)\s*(?:\|\s*([^}\s]*)\s*)?\}").unwrap();
+}
+
+/// Filters available to placeholders.
+const FILTERS: [&str; 2] = ["first", "join"];
+
+/// Output template of rule.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Template(JsonValue);
+
+/// Values available to templates other than selector results.
+#[derive(Debug)]
+pub struct Context {
+    flow: JsonValue,
+    captures: Map<String, JsonValue>,
+}
+
+impl Context {
+    /// Create context of flow, with captures of path regular expression.
+    pub fn new(resp: &Response, path: &Regex) -> Self {
+        let req = &resp.request;
+        let flow = json!({
+            "method": req.method.as_str(),
+            "url": req.uri.to_string(),
+            "host": Target::of(req).map(|target| target.host),
+            "path": req.uri.path(),
+            "status": resp.status.as_u16(),
+        });
+
+        let mut captures = Map::new();
+        if let Some(caps) = path.captures(req.uri.path()) {
+            for (index, name) in path.capture_names().enumerate() {
+                if let Some(value) = caps.get(index) {
+                    let value = json!(value.as_str());
+                    captures.insert(index.to_string(), value.clone());
+                    if let Some(name) = name {
+                        captures.insert(name.to_string(), value);
+                    }
+                }
+            }
+        }
+
+        Self { flow, captures }
+    }
+}
+
+impl Template {
+    /// Check placeholders of template are well-formed.
+    pub(crate) fn validate(&self) -> Result<()> {
+        validate(&self.0)
+    }
+
+    /// Top-level fields of template, if it is object.
+    pub(crate) fn fields(&self) -> Vec<&str> {
+        match &self.0 {
+            JsonValue::Object(map) => map.keys().map(String::as_str).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Render template with selector results and context.
+    pub fn render(&self, selected: &JsonValue, context: &Context) -> JsonValue {
+        render(&self.0, selected, context)
+    }
+}
+
+fn validate(template: &JsonValue) -> Result<()> {
+    match template {
+        JsonValue::String(s) => {
+            let stripped = PLACEHOLDER.replace_all(s, "");
+            if stripped.contains("${") {
+                bail!("malformed placeholder in template string `{s}`");
+            }
+            for caps in PLACEHOLDER.captures_iter(s) {
+                if let Some(filter) = caps.get(2) {
+                    if !FILTERS.contains(&filter.as_str()) {
+                        bail!("unknown template filter `{}`", filter.as_str());
+                    }
+                }
+            }
+        }
+        JsonValue::Array(items) => {
+            for item in items {
+                validate(item)?;
+            }
+        }
+        JsonValue::Object(map) => {
+            for value in map.values() {
+                validate(value)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn render(template: &JsonValue, selected: &JsonValue, context: &Context) -> JsonValue {
+    match template {
+        JsonValue::String(s) => {
+            // Keep type of value if string is single placeholder
+            if let Some(caps) = PLACEHOLDER.captures(s) {
+                if caps[0].len() == s.len() {
+                    return resolve(&caps, selected, context);
+                }
+            }
+
+            let rendered = PLACEHOLDER.replace_all(s, |caps: &Captures| {
+                match resolve(caps, selected, context) {
+                    JsonValue::Null => String::new(),
+                    JsonValue::String(s) => s,
+                    other => other.to_string(),
+                }
+            });
+
+            JsonValue::String(rendered.into_owned())
+        }
+        JsonValue::Array(items) => items
+            .iter()
+            .map(|item| render(item, selected, context))
+            .collect(),
+        JsonValue::Object(map) => JsonValue::Object(
+            map.iter()
+                .map(|(key, value)| (key.clone(), render(value, selected, context)))
+                .collect(),
+        ),
+        constant => constant.clone(),
+    }
+}
+
+/// Resolve placeholder to value, applying filter if any.
+fn resolve(caps: &Captures, selected: &JsonValue, context: &Context) -> JsonValue {
+    let reference = &caps[1];
+    let value = match reference.split_once('.') {
+        Some(("flow", path)) => context.flow.dot_get::<JsonValue>(path).ok().flatten(),
+        Some(("capture", name)) => context.captures.get(name).cloned(),
+        _ => selected.dot_get::<JsonValue>(reference).ok().flatten(),
+    }
+    .unwrap_or(JsonValue::Null);
+
+    match (caps.get(2).map(|m| m.as_str()), value) {
+        (Some("first"), JsonValue::Array(items)) => {
+            items.into_iter().next().unwrap_or(JsonValue::Null)
+        }
+        (Some("join"), JsonValue::Array(items)) => JsonValue::String(
+            items
+                .iter()
+                .map(|item| match item {
+                    JsonValue::String(s) => s.clone(),
+                    other => other.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+        (_, value) => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Uri;
+    use kkowa_proxy_lib::http::{Request, Response};
+    use regex::Regex;
+    use serde_json::json;
+
+    use super::{Context, Template};
+
+    fn context() -> Context {
+        let req = Request::builder()
+            .uri(Uri::from_static("http://shop.domain.com/products/p-1"))
+            .build()
+            .unwrap();
+        let resp = Response::builder().request(req).build().unwrap();
+
+        Context::new(&resp, &Regex::new(r"^/products/(?P<slug>[^/]+)$").unwrap())
+    }
+
+    #[test]
+    fn template_render() {
+        let template: Template = serde_yaml::from_str(
+            r#"
+            product:
+              id: ${capture.slug}
+              title: ${name | first} (${brand | first})
+              prices: ${prices}
+              tags: ${tags | join}
+            source: shop
+            url: ${flow.url}
+            missing: ${nothing}
+            "#,
+        )
+        .unwrap();
+        let selected = json!({
+            "name": ["Cake"],
+            "brand": ["Donuts"],
+            "prices": [1.5, 2.0],
+            "tags": ["sweet", "baked"],
+        });
+
+        assert_eq!(
+            template.render(&selected, &context()),
+            json!({
+                "product": {
+                    "id": "p-1",
+                    "title": "Cake (Donuts)",
+                    "prices": [1.5, 2.0],
+                    "tags": "sweet, baked",
+                },
+                "source": "shop",
+                "url": "http://shop.domain.com/products/p-1",
+                "missing": null,
+            })
+        );
+    }
+
+    #[test]
+    fn template_validate() {
+        let template = |s: &str| serde_yaml::from_str::<Template>(s).unwrap();
+
+        assert!(template("{ a: '${name}', b: [1, '${flow.url | first}'] }")
+            .validate()
+            .is_ok());
+        assert!(template("{ a: '${name' }").validate().is_err());
+        assert!(template("{ a: '${name | upper}' }").validate().is_err());
+    }
+}
@@ -0,0 +1,147 @@
+//! Test cases embedded in processor definitions.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use http::Method;
+use kkowa_proxy_lib::http::{Request, Response, Uri};
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+
+use super::{Output, Processor};
+
+type JsonValue = serde_json::Value;
+
+/// Test case of processor, checking document generated from fixture matches to expected one.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TestCase {
+    /// Name of test case.
+    name: Option<String>,
+
+    /// Request method.
+    #[serde(with = "http_serde::method", default)]
+    method: Method,
+
+    /// Request URL.
+    #[serde(with = "http_serde::uri")]
+    url: Uri,
+
+    /// File path of response body fixture, relative to processor definition file.
+    body: String,
+
+    /// Expected document. If not set, processor is expected to generate nothing.
+    expect: Option<JsonValue>,
+}
+
+/// Result of test case.
+#[derive(Debug)]
+pub struct CaseResult {
+    /// Name of processor.
+    pub processor: String,
+
+    /// Label of test case, name of case or its index if not named.
+    pub case: String,
+
+    /// Unified diff of expected and actual documents, if case failed.
+    pub diff: Option<String>,
+}
+
+impl CaseResult {
+    /// Whether test case passed.
+    pub fn passed(&self) -> bool {
+        self.diff.is_none()
+    }
+}
+
+/// Run test cases of processor, validating it first. Fixture paths are resolved relative to given directory.
+pub fn run(processor: &Processor, base_dir: &Path) -> Result<Vec<CaseResult>> {
+    processor
+        .validate()
+        .context("invalid processor definition")?;
+
+    let mut results = Vec::with_capacity(processor.tests().len());
+    for (index, case) in processor.tests().iter().enumerate() {
+        let label = case.name.clone().unwrap_or_else(|| format!("#{index}"));
+        let body = std::fs::read(base_dir.join(&case.body)).with_context(|| {
+            format!(
+                "failed to read fixture {body} of case {label}",
+                body = case.body
+            )
+        })?;
+
+        let req = Request::builder()
+            .method(case.method.clone())
+            .uri(case.url.clone())
+            .build()?;
+        let resp = Response::builder().payload(body).request(req).build()?;
+
+        let output = processor.evaluate(&resp);
+        let errors = output.as_ref().map_or(vec![], |o| o.errors.clone());
+        let actual = output.map(Output::into_value);
+        let diff = match (actual == case.expect, errors.is_empty()) {
+            (true, true) => None,
+            (true, false) => Some(format!("output schema errors:\n{}\n", errors.join("\n"))),
+            (false, _) => Some(diff(&case.expect, &actual)?),
+        };
+
+        results.push(CaseResult {
+            processor: processor.name().to_string(),
+            case: label,
+            diff,
+        });
+    }
+
+    Ok(results)
+}
+
+fn diff(expected: &Option<JsonValue>, actual: &Option<JsonValue>) -> Result<String> {
+    let expected = format!("{}\n", serde_json::to_string_pretty(expected)?);
+    let actual = format!("{}\n", serde_json::to_string_pretty(actual)?);
+
+    Ok(TextDiff::from_lines(&expected, &actual)
+        .unified_diff()
+        .header("expected", "actual")
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, str::FromStr};
+
+    use super::run;
+    use crate::collector::{processor::tests::donuts, Processor};
+
+    fn here() -> PathBuf {
+        PathBuf::from(file!()).parent().unwrap().to_path_buf()
+    }
+
+    #[test]
+    fn run_passed() {
+        let processor = Processor::from_str(include_str!("donuts-processor.yaml")).unwrap();
+        let results = run(&processor, &here()).unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert!(results[0].passed());
+    }
+
+    #[test]
+    fn run_failed() {
+        let processor =
+            Processor::from_str(&donuts(&[("- Old Fashioned", "- New Fashioned")])).unwrap();
+        let results = run(&processor, &here()).unwrap();
+
+        assert!(!results[0].passed());
+        assert!(results[0]
+            .diff
+            .as_ref()
+            .unwrap()
+            .contains("+      \"Old Fashioned\""));
+    }
+
+    #[test]
+    fn run_invalid() {
+        let processor = Processor::from_str(&donuts(&[("$[*].name", "$[*")])).unwrap();
+
+        assert!(run(&processor, &here()).is_err());
+    }
+}
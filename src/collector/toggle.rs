@@ -0,0 +1,202 @@
+//! Runtime toggles enabling or disabling processors and rules, overriding their definitions without redeploying.
+
+use std::{collections::BTreeMap,
+          fs,
+          path::PathBuf,
+          sync::{Arc, RwLock}};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use super::Processor;
+
+/// Shared store of toggles, optionally persisted to file across restarts.
+#[derive(Clone, Debug, Default)]
+pub struct Toggles {
+    inner: Arc<RwLock<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    state: State,
+
+    /// File path to persist toggles to.
+    path: Option<PathBuf>,
+}
+
+/// Overrides of enabled state, by processor name and rule name. Processors or rules not listed keep their default.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    pub processors: BTreeMap<String, bool>,
+
+    #[serde(default)]
+    pub rules: BTreeMap<String, BTreeMap<String, bool>>,
+}
+
+/// Change of toggle, clearing override if `enabled` is not set.
+#[derive(Debug, Deserialize)]
+pub struct Toggle {
+    /// Name of processor.
+    pub processor: String,
+
+    /// Name of rule, toggling whole processor if not set.
+    #[serde(default)]
+    pub rule: Option<String>,
+
+    pub enabled: Option<bool>,
+}
+
+impl Toggles {
+    /// Create new in-memory store without any override.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Persist toggles to given file, loading previous ones from it if exists.
+    pub fn persist(self, path: PathBuf) -> Result<Self> {
+        {
+            let mut inner = self.inner.write().unwrap();
+            if path.exists() {
+                inner.state = serde_json::from_slice(&fs::read(&path)?)
+                    .with_context(|| format!("failed to parse toggles {path:?}"))?;
+                debug!("loaded toggles from {path:?}");
+            }
+            inner.path = Some(path);
+        }
+
+        Ok(self)
+    }
+
+    /// Apply toggle, writing toggles to file if persisted.
+    pub fn set(&self, toggle: &Toggle) -> Result<()> {
+        let mut inner = self.inner.write().unwrap();
+        let state = &mut inner.state;
+        match (&toggle.rule, toggle.enabled) {
+            (None, Some(enabled)) => {
+                state.processors.insert(toggle.processor.clone(), enabled);
+            }
+            (None, None) => {
+                state.processors.remove(&toggle.processor);
+            }
+            (Some(rule), Some(enabled)) => {
+                state
+                    .rules
+                    .entry(toggle.processor.clone())
+                    .or_default()
+                    .insert(rule.clone(), enabled);
+            }
+            (Some(rule), None) => {
+                if let Some(rules) = state.rules.get_mut(&toggle.processor) {
+                    rules.remove(rule);
+                    if rules.is_empty() {
+                        state.rules.remove(&toggle.processor);
+                    }
+                }
+            }
+        }
+        info!(
+            "toggled processor {processor} rule {rule:?} to {enabled:?}",
+            processor = toggle.processor,
+            rule = toggle.rule,
+            enabled = toggle.enabled
+        );
+
+        inner.save()
+    }
+
+    /// Whether processor runs, by its override or its definition otherwise.
+    pub fn processor_enabled(&self, processor: &Processor) -> bool {
+        let inner = self.inner.read().unwrap();
+
+        inner
+            .state
+            .processors
+            .get(processor.name())
+            .copied()
+            .unwrap_or_else(|| processor.is_enabled())
+    }
+
+    /// Whether rule of processor runs. Rules are enabled unless overridden.
+    pub fn rule_enabled(&self, processor: &str, rule: &str) -> bool {
+        let inner = self.inner.read().unwrap();
+
+        inner
+            .state
+            .rules
+            .get(processor)
+            .and_then(|rules| rules.get(rule))
+            .copied()
+            .unwrap_or(true)
+    }
+
+    /// Current overrides.
+    pub fn state(&self) -> State {
+        self.inner.read().unwrap().state.clone()
+    }
+}
+
+impl Inner {
+    fn save(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&self.state)?)?;
+        fs::rename(&tmp, path)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::{Toggle, Toggles};
+    use crate::collector::Processor;
+
+    fn toggle(rule: Option<&str>, enabled: Option<bool>) -> Toggle {
+        Toggle {
+            processor: "Name".to_string(),
+            rule: rule.map(str::to_string),
+            enabled,
+        }
+    }
+
+    #[test]
+    fn toggles_override() {
+        let processor = Processor::from_str(include_str!("donuts-processor.yaml")).unwrap();
+        let toggles = Toggles::new();
+        assert!(toggles.processor_enabled(&processor));
+        assert!(toggles.rule_enabled("Name", "Donuts"));
+
+        toggles.set(&toggle(None, Some(false))).unwrap();
+        toggles.set(&toggle(Some("Donuts"), Some(false))).unwrap();
+        assert!(!toggles.processor_enabled(&processor));
+        assert!(!toggles.rule_enabled("Name", "Donuts"));
+
+        // Clearing override falls back to default
+        toggles.set(&toggle(None, None)).unwrap();
+        toggles.set(&toggle(Some("Donuts"), None)).unwrap();
+        assert!(toggles.processor_enabled(&processor));
+        assert!(toggles.rule_enabled("Name", "Donuts"));
+        assert_eq!(toggles.state(), Default::default());
+    }
+
+    #[test]
+    fn toggles_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("toggles.json");
+
+        let toggles = Toggles::new().persist(path.clone()).unwrap();
+        toggles.set(&toggle(Some("Donuts"), Some(false))).unwrap();
+
+        let toggles = Toggles::new().persist(path).unwrap();
+        assert!(!toggles.rule_enabled("Name", "Donuts"));
+    }
+}
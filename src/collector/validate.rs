@@ -0,0 +1,328 @@
+//! Processor definition validation module.
+
+use std::{fmt,
+          path::{Path, PathBuf},
+          str::FromStr};
+
+use anyhow::Result;
+
+use super::{bundle::TrustedKeys, compose::Definitions, loader, Processor};
+
+/// Problem found from processor definition.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Issue {
+    /// File problem found from.
+    pub file: Option<PathBuf>,
+
+    /// Line number of problem, starting from 1.
+    pub line: Option<usize>,
+
+    /// Label of rule problem found from.
+    pub rule: Option<String>,
+
+    /// Description of problem.
+    pub message: String,
+
+    /// Whether problem is only warning, not preventing processor from being loaded.
+    pub warning: bool,
+
+    /// Index of rule problem found from.
+    index: Option<usize>,
+
+    /// Text to look for to locate line of problem in rule.
+    hint: Option<String>,
+}
+
+impl Issue {
+    /// Create new issue.
+    pub fn new<S>(message: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            message: message.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Set rule of issue.
+    pub fn rule(mut self, index: usize, label: String) -> Self {
+        self.index = Some(index);
+        self.rule = Some(label);
+        self
+    }
+
+    /// Mark issue as warning.
+    pub fn warning(mut self) -> Self {
+        self.warning = true;
+        self
+    }
+
+    /// Set text to look for to locate line of issue.
+    pub fn hint(mut self, hint: &str) -> Self {
+        self.hint = Some(hint.to_string());
+        self
+    }
+
+    fn file(mut self, file: &Path) -> Self {
+        self.file = Some(file.to_path_buf());
+        self
+    }
+
+    fn line(mut self, line: Option<usize>) -> Self {
+        self.line = line;
+        self
+    }
+
+    fn from_yaml(err: &serde_yaml::Error) -> Self {
+        Self::new(err.to_string()).line(err.location().map(|l| l.line()))
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}", file.display())?;
+            if let Some(line) = self.line {
+                write!(f, ":{line}")?;
+            }
+            write!(f, ": ")?;
+        }
+        if let Some(rule) = &self.rule {
+            write!(f, "rule {rule}: ")?;
+        }
+        if self.warning {
+            write!(f, "warning: ")?;
+        }
+
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Validate processor definitions at given path the same way those are loaded, reporting all problems found.
+pub fn validate(path: &Path, keys: &TrustedKeys) -> Result<Vec<Issue>> {
+    let definitions = Definitions::read(path)?;
+    let mut issues = Vec::new();
+    for def in loader::discover(path)? {
+        if def
+            .extension()
+            .map_or(false, |ext| ext == loader::BUNDLE_EXTENSION)
+        {
+            if let Err(err) = loader::load_bundle(&def, keys) {
+                issues.push(Issue::new(format!("{err:#}")).file(&def));
+            }
+
+            continue;
+        }
+
+        if !keys.is_empty() {
+            issues.push(
+                Issue::new("unsigned processor def rejected as trusted keys are configured")
+                    .file(&def),
+            );
+
+            continue;
+        }
+
+        // Libraries and abstract processors are checked as part of processors using them
+        let composed = definitions.is_composed(&def);
+        if !composed && matches!(definitions.resolve(&def), Ok(None)) {
+            continue;
+        }
+
+        let source = match composed {
+            true => definitions
+                .resolve(&def)
+                .and_then(|resolved| Ok(serde_yaml::to_string(&resolved.unwrap_or_default())?)),
+            false => std::fs::read_to_string(&def).map_err(Into::into),
+        };
+        let source = match source {
+            Ok(source) => source,
+            Err(err) => {
+                issues.push(Issue::new(format!("{err:#}")).file(&def));
+                continue;
+            }
+        };
+
+        let found = validate_source(&source);
+        if found.is_empty() {
+            // Check resources referred by definition, such as output schema file
+            if let Err(err) = definitions.load(&def) {
+                issues.push(Issue::new(format!("{err:#}")).file(&def));
+            }
+        }
+        issues.extend(found.into_iter().map(|issue| match composed {
+            // Lines of resolved definition do not correspond to file
+            true => issue.line(None).file(&def),
+            false => issue.file(&def),
+        }));
+    }
+
+    Ok(issues)
+}
+
+/// Validate processor definition source, reporting all problems found.
+pub fn validate_source(source: &str) -> Vec<Issue> {
+    let value: serde_yaml::Value = match serde_yaml::from_str(source) {
+        Ok(value) => value,
+        Err(err) => return vec![Issue::from_yaml(&err)],
+    };
+    let rule_lines = rule_lines(source);
+
+    let issues = match Processor::from_str(source) {
+        Ok(processor) => processor.issues(),
+        Err(err) => {
+            // Deserialize each rule to report schema errors of all rules, not only the first one
+            let mut issues: Vec<Issue> = Processor::rule_errors(&value)
+                .into_iter()
+                .map(|(index, err)| {
+                    let label = value["spec"]["rules"][index]["name"]
+                        .as_str()
+                        .map_or_else(|| format!("#{index}"), str::to_string);
+
+                    Issue::new(err.to_string()).rule(index, label)
+                })
+                .collect();
+
+            let outside_rules = match (err.location(), rule_lines.first()) {
+                (Some(location), Some(first)) => location.line() < *first,
+                _ => true,
+            };
+            if issues.is_empty() || outside_rules {
+                issues.insert(0, Issue::from_yaml(&err));
+            }
+
+            issues
+        }
+    };
+
+    issues
+        .into_iter()
+        .map(|issue| locate(issue, source, &rule_lines))
+        .collect()
+}
+
+/// Find line of issue within its rule, if not known yet.
+fn locate(issue: Issue, source: &str, rule_lines: &[usize]) -> Issue {
+    if issue.line.is_some() {
+        return issue;
+    }
+
+    let (start, end) = match issue.index.and_then(|index| rule_lines.get(index)) {
+        Some(start) => (
+            *start,
+            rule_lines
+                .get(issue.index.unwrap() + 1)
+                .copied()
+                .unwrap_or(usize::MAX),
+        ),
+        None => return issue,
+    };
+
+    let found = issue.hint.as_ref().and_then(|hint| {
+        source
+            .lines()
+            .enumerate()
+            .map(|(n, line)| (n + 1, line))
+            .skip(start - 1)
+            .take_while(|(n, _)| *n < end)
+            .find(|(_, line)| line.contains(hint.as_str()))
+            .map(|(n, _)| n)
+    });
+
+    let line = Some(found.unwrap_or(start));
+    issue.line(line)
+}
+
+/// Find starting lines of rules in processor definition source.
+fn rule_lines(source: &str) -> Vec<usize> {
+    let mut lines = Vec::new();
+    let mut rules_indent = None;
+    let mut item_indent = None;
+    for (n, line) in source.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let indent = line.len() - trimmed.len();
+        match rules_indent {
+            None => {
+                if trimmed.starts_with("rules:") {
+                    rules_indent = Some(indent);
+                }
+            }
+            Some(rules_indent) => {
+                if indent < rules_indent || (indent == rules_indent && !trimmed.starts_with('-')) {
+                    break;
+                }
+                if trimmed.starts_with('-') && *item_indent.get_or_insert(indent) == indent {
+                    lines.push(n + 1);
+                }
+            }
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{rule_lines, validate, validate_source};
+    use crate::collector::{bundle::TrustedKeys, processor::tests::donuts};
+
+    const SOURCE: &str = include_str!("donuts-processor.yaml");
+
+    #[test]
+    fn validate_directory() {
+        let path = PathBuf::from(file!()).parent().unwrap().to_path_buf();
+
+        assert!(validate(&path, &TrustedKeys::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn validate_source_syntax() {
+        let issues = validate_source("metadata: [");
+
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].line.is_some());
+    }
+
+    #[test]
+    fn validate_source_selector() {
+        let issues = validate_source(&donuts(&[("$[*].name", "$[*")]));
+
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].rule.as_deref(), Some("Donuts"));
+        assert_eq!(issues[0].line, Some(22));
+    }
+
+    #[test]
+    fn validate_source_rules() {
+        // Two broken rules, both should be reported
+        let source = donuts(&[
+            (
+                "\ntests:",
+                "\n    - method: GET\n      path: ^/(\n      request:\n        selectors: []\n      response:\n        selectors: []\n\ntests:",
+            ),
+            (
+                "method: GET\n      path: ^/donuts$",
+                "method: GET\n      path: ^/donuts($",
+            ),
+        ]);
+        let issues = validate_source(&source);
+
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0].rule.as_deref(), Some("Donuts"));
+        assert_eq!(issues[0].line, Some(9));
+        assert_eq!(issues[1].rule.as_deref(), Some("#1"));
+        assert_eq!(issues[1].line, Some(24));
+    }
+
+    #[test]
+    fn rule_lines_of_source() {
+        assert_eq!(rule_lines(SOURCE), vec![9]);
+    }
+}
@@ -0,0 +1,280 @@
+//! Versions of processor definition format and migrations between those.
+//!
+//! Definitions without `apiVersion` are of legacy format, where unknown fields are ignored. Definitions of current
+//! version reject unknown fields, so typos and fields of newer versions do not go unnoticed.
+
+use anyhow::{bail, Result};
+use serde_yaml::{Mapping, Value};
+use tracing::warn;
+
+use super::Processor;
+
+/// Current version of processor definition format.
+pub const API_VERSION: &str = "collector.kkowa.io/v1";
+
+/// Kind of processor definitions.
+pub const KIND: &str = "Processor";
+
+/// Check version header of definition, with paths of fields ignored while deserializing it.
+pub(crate) fn check(
+    api_version: Option<&str>,
+    kind: Option<&str>,
+    name: &str,
+    ignored: &[String],
+) -> Result<(), String> {
+    match (api_version, kind) {
+        (None, _) => {
+            if !ignored.is_empty() {
+                warn!(
+                    "legacy processor definition {name} has unknown fields {}, which are ignored; run `migrate` to upgrade it",
+                    ignored.join(", ")
+                );
+            }
+
+            Ok(())
+        }
+        (Some(API_VERSION), Some(KIND)) if ignored.is_empty() => Ok(()),
+        (Some(API_VERSION), Some(KIND)) => Err(format!(
+            "unknown field(s) for {API_VERSION}: {}",
+            ignored.join(", ")
+        )),
+        (Some(API_VERSION), kind) => Err(format!("kind must be `{KIND}`, but got {kind:?}")),
+        (Some(other), _) => Err(format!(
+            "unsupported apiVersion `{other}`, expected `{API_VERSION}`"
+        )),
+    }
+}
+
+/// Result of migrating definition to current version.
+#[derive(Debug)]
+pub struct Migration {
+    /// Upgraded definition source.
+    pub source: String,
+
+    /// Fields ignored by older version and dropped.
+    pub dropped: Vec<String>,
+}
+
+/// Outcome of migrating definition.
+#[derive(Debug)]
+pub enum Outcome {
+    /// Already of current version, or rule library having no version.
+    UpToDate,
+
+    /// Left as is for given reason, to be migrated by hand.
+    Skipped(String),
+
+    /// Upgraded to current version.
+    Migrated(Migration),
+}
+
+/// Upgrade definition source to current version.
+///
+/// If fields ignored by older version are dropped, whole definition is rewritten and comments are lost; otherwise
+/// only version header is inserted.
+pub fn migrate(source: &str) -> Result<Outcome> {
+    let value: Value = serde_yaml::from_str(source)?;
+    if value.get("fragments").is_some() {
+        return Ok(Outcome::UpToDate);
+    }
+
+    match value.get("apiVersion").map(Value::as_str) {
+        Some(Some(API_VERSION)) => Ok(Outcome::UpToDate),
+        Some(other) => bail!("unsupported apiVersion {other:?}, can't migrate"),
+        // Unknown fields of composed definitions can't be told by themselves, and header alone would make those
+        // rejected once resolved
+        None if is_composed(&value) => Ok(Outcome::Skipped(
+            "uses `extends`, `include` or `abstract`".to_string(),
+        )),
+        None => v0_to_v1(source, value).map(Outcome::Migrated),
+    }
+}
+
+/// Whether definition is composed with others by `extends` or `include`, or is abstract base of others.
+fn is_composed(value: &Value) -> bool {
+    value.get("extends").is_some()
+        || value.get("abstract").is_some()
+        || value["spec"]["rules"].as_sequence().map_or(false, |rules| {
+            rules.iter().any(|rule| rule.get("include").is_some())
+        })
+}
+
+/// Key or index of field in definition.
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Upgrade legacy definition, dropping unknown fields.
+fn v0_to_v1(source: &str, value: Value) -> Result<Migration> {
+    let mut ignored = Vec::new();
+    serde_ignored::deserialize::<_, _, Processor>(value.clone(), |path| {
+        let mut segments = Vec::new();
+        segments_of(&path, &mut segments);
+        ignored.push((path.to_string(), segments));
+    })?;
+
+    if ignored.is_empty() {
+        return Ok(Migration {
+            source: with_header(source),
+            dropped: vec![],
+        });
+    }
+
+    let mut value = value;
+    for (_, segments) in &ignored {
+        remove(&mut value, segments);
+    }
+    let mut upgraded = Mapping::new();
+    upgraded.insert("apiVersion".into(), API_VERSION.into());
+    upgraded.insert("kind".into(), KIND.into());
+    if let Value::Mapping(map) = value {
+        upgraded.extend(map);
+    }
+
+    Ok(Migration {
+        source: serde_yaml::to_string(&upgraded)?,
+        dropped: ignored.into_iter().map(|(path, _)| path).collect(),
+    })
+}
+
+fn segments_of(path: &serde_ignored::Path, segments: &mut Vec<Segment>) {
+    match path {
+        serde_ignored::Path::Root => {}
+        serde_ignored::Path::Seq { parent, index } => {
+            segments_of(parent, segments);
+            segments.push(Segment::Index(*index));
+        }
+        serde_ignored::Path::Map { parent, key } => {
+            segments_of(parent, segments);
+            segments.push(Segment::Key(key.clone()));
+        }
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => segments_of(parent, segments),
+    }
+}
+
+/// Remove field at given path from definition.
+fn remove(value: &mut Value, segments: &[Segment]) {
+    match segments {
+        [] => {}
+        [Segment::Key(key)] => {
+            if let Some(map) = value.as_mapping_mut() {
+                map.remove(key.as_str());
+            }
+        }
+        [Segment::Index(_)] => {}
+        [Segment::Key(key), rest @ ..] => {
+            if let Some(child) = value.get_mut(key.as_str()) {
+                remove(child, rest);
+            }
+        }
+        [Segment::Index(index), rest @ ..] => {
+            if let Some(child) = value.get_mut(*index) {
+                remove(child, rest);
+            }
+        }
+    }
+}
+
+/// Insert version header before first line of contents, keeping leading comments and rest of source as is.
+fn with_header(source: &str) -> String {
+    let header = format!("apiVersion: {API_VERSION}\nkind: {KIND}\n");
+    let mut offset = 0;
+    for line in source.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if !(trimmed.is_empty() || trimmed.starts_with('#') || trimmed == "---") {
+            break;
+        }
+        offset += line.len();
+    }
+
+    format!("{}{header}{}", &source[..offset], &source[offset..])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rstest::*;
+
+    use super::{check, migrate, Outcome, API_VERSION};
+    use crate::collector::{processor::tests::{donuts, substitute},
+                           Processor};
+
+    const SOURCE: &str = include_str!("donuts-processor.yaml");
+
+    #[test]
+    fn version_check() {
+        assert!(check(None, None, "Name", &["unknown".to_string()]).is_ok());
+        assert!(check(Some(API_VERSION), Some("Processor"), "Name", &[]).is_ok());
+        assert!(check(
+            Some(API_VERSION),
+            Some("Processor"),
+            "Name",
+            &["unknown".to_string()]
+        )
+        .is_err());
+        assert!(check(Some(API_VERSION), None, "Name", &[]).is_err());
+        assert!(check(
+            Some("collector.kkowa.io/v9"),
+            Some("Processor"),
+            "Name",
+            &[]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn migrate_header() {
+        let migration = match migrate(SOURCE).unwrap() {
+            Outcome::Migrated(migration) => migration,
+            outcome => panic!("unexpected outcome {outcome:?}"),
+        };
+
+        assert!(migration.dropped.is_empty());
+        assert!(migration
+            .source
+            .starts_with("# Sample processor definition for testing.\n"));
+        assert!(migration
+            .source
+            .contains(&format!("apiVersion: {API_VERSION}\nkind: Processor\n")));
+        Processor::from_str(&migration.source).unwrap();
+
+        // Already up to date
+        assert!(matches!(
+            migrate(&migration.source).unwrap(),
+            Outcome::UpToDate
+        ));
+    }
+
+    #[test]
+    fn migrate_unknown_fields() {
+        let source = donuts(&[("  name: Name\n", "  name: Name\n  maintainer: someone\n")]);
+        let migration = match migrate(&source).unwrap() {
+            Outcome::Migrated(migration) => migration,
+            outcome => panic!("unexpected outcome {outcome:?}"),
+        };
+
+        assert_eq!(migration.dropped, vec!["metadata.maintainer".to_string()]);
+        Processor::from_str(&migration.source).unwrap();
+
+        // Rejected by current version
+        let strict = substitute(
+            &migration.source,
+            &[("  name: Name\n", "  name: Name\n  maintainer: someone\n")],
+        );
+        assert!(Processor::from_str(&strict).is_err());
+    }
+
+    #[rstest]
+    #[case("metadata:\n", "extends: Shop\nmetadata:\n")]
+    #[case("metadata:\n", "abstract: true\nmetadata:\n")]
+    #[case("  rules:\n", "  rules:\n    - include: json-get\n")]
+    fn migrate_composed(#[case] from: &str, #[case] to: &str) {
+        let source = donuts(&[(from, to)]);
+
+        assert!(matches!(migrate(&source).unwrap(), Outcome::Skipped(_)));
+    }
+}
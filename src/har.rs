@@ -0,0 +1,400 @@
+//! HTTP Archive (HAR) 1.2 format module.
+//!
+//! Only the subset of format required to reproduce flows is modeled, see <http://www.softwareishard.com/blog/har-12-spec/>.
+
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use http::{header::{CONTENT_TYPE, LOCATION},
+           HeaderMap, HeaderName, HeaderValue};
+use kkowa_proxy_lib::http::{Headers, Method, Request, Response, StatusCode, Uri, Version};
+use serde::{Deserialize, Serialize};
+
+/// Root of HAR document.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Har {
+    pub log: Log,
+}
+
+impl FromStr for Har {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Log {
+    pub version: String,
+    pub creator: Creator,
+    #[serde(default)]
+    pub entries: Vec<Entry>,
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Self {
+            version: "1.2".to_string(),
+            creator: Creator {
+                name: env!("CARGO_PKG_NAME").to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+            },
+            entries: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Creator {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    pub started_date_time: String,
+    #[serde(default)]
+    pub time: f64,
+    pub request: EntryRequest,
+    pub response: EntryResponse,
+    #[serde(default)]
+    pub cache: serde_json::Value,
+    #[serde(default)]
+    pub timings: Timings,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntryRequest {
+    pub method: String,
+    pub url: String,
+    pub http_version: String,
+    #[serde(default)]
+    pub cookies: Vec<serde_json::Value>,
+    #[serde(default)]
+    pub headers: Vec<Header>,
+    #[serde(default)]
+    pub query_string: Vec<Header>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_data: Option<PostData>,
+    #[serde(default = "unknown_size")]
+    pub headers_size: i64,
+    #[serde(default = "unknown_size")]
+    pub body_size: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntryResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub status_text: String,
+    pub http_version: String,
+    #[serde(default)]
+    pub cookies: Vec<serde_json::Value>,
+    #[serde(default)]
+    pub headers: Vec<Header>,
+    pub content: Content,
+    #[serde(default, rename = "redirectURL")]
+    pub redirect_url: String,
+    #[serde(default = "unknown_size")]
+    pub headers_size: i64,
+    #[serde(default = "unknown_size")]
+    pub body_size: i64,
+}
+
+/// Name-value pair used for headers and query parameters.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostData {
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(default)]
+    pub text: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Content {
+    #[serde(default)]
+    pub size: i64,
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Timings {
+    pub send: f64,
+    pub wait: f64,
+    pub receive: f64,
+}
+
+/// Replacement for redacted values.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Headers redacted by default, as those carry credentials.
+pub const DEFAULT_REDACT: [&str; 4] = [
+    "authorization",
+    "cookie",
+    "proxy-authorization",
+    "set-cookie",
+];
+
+fn unknown_size() -> i64 {
+    -1
+}
+
+impl Entry {
+    /// Create entry from flow response, with its request.
+    pub fn from_response(resp: &Response, started: DateTime<Utc>) -> Self {
+        let req = &resp.request;
+        let post_data = match req.payload.is_empty() {
+            true => None,
+            false => Some(PostData {
+                mime_type: header_value(&req.headers, CONTENT_TYPE.as_str()).unwrap_or_default(),
+                text: String::from_utf8_lossy(&req.payload).to_string(),
+            }),
+        };
+        let query_string = req
+            .uri
+            .query()
+            .map(|query| {
+                query
+                    .split('&')
+                    .filter(|pair| !pair.is_empty())
+                    .map(|pair| {
+                        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                        Header {
+                            name: name.to_string(),
+                            value: value.to_string(),
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // Keep text as is if possible, otherwise encode as base64
+        let (text, encoding) = match std::str::from_utf8(&resp.payload) {
+            Ok(text) => (text.to_string(), None),
+            Err(_) => (base64::encode(&resp.payload), Some("base64".to_string())),
+        };
+
+        Self {
+            started_date_time: started.to_rfc3339_opts(SecondsFormat::Millis, true),
+            time: 0.0,
+            request: EntryRequest {
+                method: req.method.to_string(),
+                url: req.uri.to_string(),
+                http_version: format!("{:?}", req.version),
+                cookies: vec![],
+                headers: from_headers(&req.headers),
+                query_string,
+                post_data,
+                headers_size: -1,
+                body_size: req.payload.len() as i64,
+            },
+            response: EntryResponse {
+                status: resp.status.as_u16(),
+                status_text: resp
+                    .status
+                    .canonical_reason()
+                    .unwrap_or_default()
+                    .to_string(),
+                http_version: format!("{:?}", resp.version),
+                cookies: vec![],
+                headers: from_headers(&resp.headers),
+                content: Content {
+                    size: resp.payload.len() as i64,
+                    mime_type: header_value(&resp.headers, CONTENT_TYPE.as_str())
+                        .unwrap_or_default(),
+                    text: Some(text),
+                    encoding,
+                },
+                redirect_url: header_value(&resp.headers, LOCATION.as_str()).unwrap_or_default(),
+                headers_size: -1,
+                body_size: resp.payload.len() as i64,
+            },
+            cache: serde_json::json!({}),
+            timings: Timings::default(),
+        }
+    }
+
+    /// Replace values of headers with given names, both of request and response, case-insensitively.
+    pub fn redact(&mut self, names: &[String]) {
+        for header in self
+            .request
+            .headers
+            .iter_mut()
+            .chain(self.response.headers.iter_mut())
+        {
+            if names
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(&header.name))
+            {
+                header.value = REDACTED.to_string();
+            }
+        }
+    }
+
+    /// Build flow response, with its request, from entry.
+    pub fn to_response(&self) -> Result<Response> {
+        let req = &self.request;
+        let payload = req
+            .post_data
+            .as_ref()
+            .map(|data| data.text.as_bytes().to_vec())
+            .unwrap_or_default();
+        let request = Request::new(
+            Method::from_str(&req.method).context("invalid request method")?,
+            Uri::from_str(&req.url).context("invalid request URL")?,
+            parse_version(&req.http_version),
+            to_headers(&req.headers),
+            payload,
+        );
+
+        let resp = &self.response;
+        let payload = match (&resp.content.text, resp.content.encoding.as_deref()) {
+            (Some(text), Some("base64")) => {
+                base64::decode(text).context("invalid base64 response content")?
+            }
+            (Some(text), _) => text.as_bytes().to_vec(),
+            (None, _) => vec![],
+        };
+
+        Ok(Response::new(
+            StatusCode::from_u16(resp.status).context("invalid response status")?,
+            parse_version(&resp.http_version),
+            to_headers(&resp.headers),
+            payload,
+            request,
+        ))
+    }
+}
+
+fn parse_version(s: &str) -> Version {
+    match s.to_uppercase().as_str() {
+        "HTTP/0.9" => Version::HTTP_09,
+        "HTTP/1.0" => Version::HTTP_10,
+        "HTTP/2" | "HTTP/2.0" | "H2" => Version::HTTP_2,
+        "HTTP/3" | "HTTP/3.0" | "H3" => Version::HTTP_3,
+        _ => Version::HTTP_11,
+    }
+}
+
+/// Convert flow headers to HAR headers.
+fn from_headers(headers: &Headers) -> Vec<Header> {
+    headers
+        .iter()
+        .map(|(name, value)| Header {
+            name: name.to_string(),
+            value: String::from_utf8_lossy(value.as_bytes()).to_string(),
+        })
+        .collect()
+}
+
+fn header_value(headers: &Headers, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).to_string())
+}
+
+/// Convert HAR headers to flow headers, skipping invalid ones.
+fn to_headers(headers: &[Header]) -> Headers {
+    let mut map = HeaderMap::new();
+    for header in headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_str(&header.name),
+            HeaderValue::from_str(&header.value),
+        ) {
+            map.append(name, value);
+        }
+    }
+
+    Headers::from(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::Utc;
+    use kkowa_proxy_lib::http::{Method, StatusCode};
+
+    use super::{Entry, Har, Header, DEFAULT_REDACT, REDACTED};
+
+    #[test]
+    fn har_from_str() {
+        let har = Har::from_str(include_str!("collector/donuts.har")).unwrap();
+
+        assert_eq!(har.log.entries.len(), 2);
+    }
+
+    #[test]
+    fn entry_from_response() {
+        let har = Har::from_str(include_str!("collector/donuts.har")).unwrap();
+        let resp = har.log.entries[1].to_response().unwrap();
+        let mut entry = Entry::from_response(&resp, Utc::now());
+
+        assert_eq!(entry.request.url, "http://subdomain.domain.com/other");
+        assert_eq!(entry.request.http_version, "HTTP/1.1");
+        assert_eq!(entry.response.status, 200);
+        assert_eq!(
+            entry.response.content.text.as_deref(),
+            Some(r#"{"name":"Other"}"#)
+        );
+
+        entry.redact(&["accept".to_string()]);
+        assert!(entry
+            .request
+            .headers
+            .iter()
+            .any(|h| h.name.eq_ignore_ascii_case("accept") && h.value == REDACTED));
+    }
+
+    #[test]
+    fn entry_redact_default() {
+        let mut entry = Har::from_str(include_str!("collector/donuts.har"))
+            .unwrap()
+            .log
+            .entries
+            .remove(0);
+        entry.response.headers.push(Header {
+            name: "Set-Cookie".to_string(),
+            value: "session=secret".to_string(),
+        });
+
+        entry.redact(&DEFAULT_REDACT.map(str::to_string));
+        assert!(entry
+            .response
+            .headers
+            .iter()
+            .any(|h| h.name == "Set-Cookie" && h.value == REDACTED));
+    }
+
+    #[test]
+    fn entry_to_response() {
+        let har = Har::from_str(include_str!("collector/donuts.har")).unwrap();
+        let resp = har.log.entries[0].to_response().unwrap();
+
+        assert_eq!(resp.status, StatusCode::OK);
+        assert_eq!(resp.request.method, Method::GET);
+        assert_eq!(resp.request.uri.path(), "/donuts");
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&resp.payload).unwrap()[0]["name"],
+            "Cake"
+        );
+    }
+}
@@ -1,5 +1,7 @@
 pub mod auth;
 pub mod collector;
+pub mod har;
+pub mod recorder;
 pub mod web;
 
 use tracing::Level;
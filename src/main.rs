@@ -1,13 +1,34 @@
 //! Main binary for use by kkowa application system.
 
-use std::{net::SocketAddr, path::PathBuf};
+use std::{fs::File,
+          io::BufWriter,
+          net::SocketAddr,
+          path::{Path, PathBuf},
+          str::FromStr,
+          time::Duration};
 
-use clap::Parser;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
 use kkowa_proxy_collector::{auth::Delegator,
-                            collector::{Collector, Processor},
+                            collector::{archive::{self, Archive, Archiver},
+                                        bundle::TrustedKeys,
+                                        change::ChangeStore,
+                                        compose::Definitions,
+                                        dedup::Dedup,
+                                        discovery::Discovery,
+                                        loader, redact,
+                                        remote::Fetcher,
+                                        replay,
+                                        schema::DeadLetter,
+                                        suggest, testing,
+                                        toggle::Toggles,
+                                        validate, version, Collector, Processors, Reloader},
+                            har::Har,
                             init_logging, init_metrics, init_tracing,
+                            recorder::Recorder,
                             web::Web};
-use kkowa_proxy_lib::{http::Uri, Proxy};
+use kkowa_proxy_lib::{auth::Credentials, http::Uri, proxy::Handler, Proxy};
+use regex::Regex;
 use tracing::Level;
 
 macro_rules! arg_env {
@@ -21,6 +42,9 @@ macro_rules! arg_env {
 #[derive(Clone, Debug, Parser)]
 #[clap(author, version, about, long_about = None)]
 struct Config {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Host address the proxy server listen to
     #[clap(long, env = arg_env!("HOST"), default_value = "0.0.0.0")]
     host: String,
@@ -46,15 +70,259 @@ struct Config {
     server: Option<Uri>,
 
     /// File or directory path for processor definition file(s). If path is directory, try to load all YAML files in
-    /// directory as processor. If not set, load default processors.
+    /// directory as processor. If not set, load default processors. Processors are reloaded when files change or on
+    /// SIGHUP.
     #[clap(short, long, env = arg_env!("PROCESSOR"))]
     processor: Option<PathBuf>,
+
+    /// Path of processor bundle endpoint on core server. If set, processors are polled from server instead of watching
+    /// processor path, which then only used if there is no cached bundle.
+    #[clap(long, env = arg_env!("PROCESSOR_ENDPOINT"), requires = "server")]
+    processor_endpoint: Option<String>,
+
+    /// File path to cache last good processor bundle fetched from server, for offline starts.
+    #[clap(long, env = arg_env!("PROCESSOR_CACHE"))]
+    processor_cache: Option<PathBuf>,
+
+    /// Interval in seconds to poll processor bundle endpoint.
+    #[clap(
+        long,
+        env = arg_env!("PROCESSOR_POLL_INTERVAL"),
+        default_value = "60",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    processor_poll_interval: u64,
+
+    /// Base64-encoded ed25519 public key trusted to sign processor bundles. If any key set, only signed bundles are
+    /// loaded. Remote bundles always require signature of trusted key.
+    #[clap(long = "trusted-key", env = arg_env!("TRUSTED_KEYS"), value_delimiter = ',')]
+    trusted_keys: Vec<String>,
+
+    /// Directory to record proxied flows to as rotating HAR files. If not set, flows are not recorded.
+    #[clap(long, env = arg_env!("RECORD"))]
+    record: Option<PathBuf>,
+
+    /// Record only flows of hosts matching regular expression.
+    #[clap(long, env = arg_env!("RECORD_HOST"))]
+    record_host: Option<Regex>,
+
+    /// Record only flows of user, username for basic auth or token for bearer auth.
+    #[clap(long, env = arg_env!("RECORD_USERS"), value_delimiter = ',')]
+    record_user: Vec<String>,
+
+    /// Names of headers to redact in recorded flows.
+    #[clap(
+        long,
+        env = arg_env!("RECORD_REDACT"),
+        value_delimiter = ',',
+        default_value = "authorization,cookie,proxy-authorization,set-cookie"
+    )]
+    record_redact: Vec<String>,
+
+    /// Maximum number of flows per recorded HAR file.
+    #[clap(long, env = arg_env!("RECORD_MAX_ENTRIES"), default_value = "100")]
+    record_max_entries: usize,
+
+    /// Maximum number of recorded HAR files to keep, removing oldest ones.
+    #[clap(long, env = arg_env!("RECORD_MAX_FILES"), default_value = "10")]
+    record_max_files: usize,
+
+    /// Directory to archive raw flows of hosts matched by any processor, for reprocessing later with `reprocess`. If
+    /// not set, flows are not archived.
+    #[clap(long, env = arg_env!("ARCHIVE"))]
+    archive: Option<PathBuf>,
+
+    /// Maximum total size of archive in megabytes, removing oldest segments exceeding it.
+    #[clap(long, env = arg_env!("ARCHIVE_MAX_SIZE"), default_value = "1024")]
+    archive_max_size: u64,
+
+    /// Names of headers to redact in archived flows.
+    #[clap(
+        long,
+        env = arg_env!("ARCHIVE_REDACT"),
+        value_delimiter = ',',
+        default_value = "authorization,cookie,proxy-authorization,set-cookie"
+    )]
+    archive_redact: Vec<String>,
+
+    /// Maximum age of archived flows in hours.
+    #[clap(long, env = arg_env!("ARCHIVE_MAX_AGE"), default_value = "168")]
+    archive_max_age: u64,
+
+    /// Sample JSON flows matched by no processor rule, to discover endpoints not covered yet. Samples are served on
+    /// web server at `/discovery`.
+    #[clap(long, env = arg_env!("DISCOVERY"))]
+    discovery: bool,
+
+    /// Maximum number of new endpoints sampled per minute.
+    #[clap(long, env = arg_env!("DISCOVERY_RATE"), default_value = "60")]
+    discovery_rate: usize,
+
+    /// Maximum number of endpoints sampled.
+    #[clap(long, env = arg_env!("DISCOVERY_CAPACITY"), default_value = "1000")]
+    discovery_capacity: usize,
+
+    /// File path to persist last documents of `emit: on-change` rules to, for change detection across restarts. If
+    /// not set, kept in memory only.
+    #[clap(long, env = arg_env!("CHANGE_STORE"))]
+    change_store: Option<PathBuf>,
+
+    /// Maximum number of document keys kept for change detection.
+    #[clap(long, env = arg_env!("CHANGE_STORE_CAPACITY"), default_value = "10000")]
+    change_store_capacity: usize,
+
+    /// Bearer token of admin endpoints of web server, such as `/toggles` enabling or disabling processors and rules
+    /// at runtime. If not set, admin endpoints are not available.
+    #[clap(long, env = arg_env!("ADMIN_TOKEN"), hide_env_values = true)]
+    admin_token: Option<String>,
+
+    /// File path to persist runtime toggles of processors and rules to, to keep those across restarts. If not set,
+    /// kept in memory only.
+    #[clap(long, env = arg_env!("TOGGLES"))]
+    toggles: Option<PathBuf>,
+
+    /// Seconds to suppress uploads of documents identical to one uploaded by same user to same folder. If zero,
+    /// duplicates are not suppressed.
+    #[clap(long, env = arg_env!("DEDUP_TTL"), default_value = "60")]
+    dedup_ttl: u64,
+
+    /// Maximum number of recent documents remembered for deduplication.
+    #[clap(long, env = arg_env!("DEDUP_CAPACITY"), default_value = "10000")]
+    dedup_capacity: usize,
+
+    /// File path of global redaction policy, YAML list of redaction rules applied to every document after ones of
+    /// processors.
+    #[clap(long, env = arg_env!("REDACT_POLICY"))]
+    redact_policy: Option<PathBuf>,
+
+    /// Salt of hashes of redacted values, required if any redaction rule hashes values.
+    #[clap(long, env = arg_env!("REDACT_SALT"), hide_env_values = true)]
+    redact_salt: Option<String>,
+
+    /// File path to append documents not conforming to output schema of processor to, as NDJSON with validation
+    /// errors. If not set, those are dropped.
+    #[clap(long, env = arg_env!("DEAD_LETTER"))]
+    dead_letter: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum Command {
+    /// Validate processor definition file(s) and report all problems found, without running server.
+    Validate {
+        /// File or directory path for processor definition file(s), loaded the same way as `--processor`.
+        path: PathBuf,
+    },
+
+    /// Upgrade processor definition file(s) to current format version, rewriting those in place. Definitions composed
+    /// with `extends` or `include` are skipped.
+    Migrate {
+        /// File or directory path for processor definition file(s).
+        path: PathBuf,
+
+        /// Print changes without writing files.
+        #[clap(long)]
+        dry_run: bool,
+    },
+
+    /// Run test cases embedded in processor definition file(s) and print diffs of mismatches.
+    Test {
+        /// File or directory path for processor definition file(s).
+        path: PathBuf,
+    },
+
+    /// Run processors loaded from `--processor` against HAR file, writing generated documents as NDJSON.
+    Replay {
+        /// HAR file path.
+        har: PathBuf,
+
+        /// File path to write documents to. If not set, write to standard output.
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Re-run processors loaded from `--processor` over archived flows and upload documents to `--server` with
+    /// `--token`. If either not set, only report what would be generated.
+    Reprocess {
+        /// Archive directory.
+        archive: PathBuf,
+
+        /// Start of time range, in RFC 3339 format.
+        #[clap(long)]
+        from: DateTime<Utc>,
+
+        /// End of time range, in RFC 3339 format. Defaults to now.
+        #[clap(long)]
+        to: Option<DateTime<Utc>>,
+
+        /// Bearer token to upload documents with. Only flows made by user of token are uploaded, as archive keeps
+        /// no credentials. If not set, only report what would be generated.
+        #[clap(long, env = arg_env!("REPROCESS_TOKEN"), hide_env_values = true)]
+        token: Option<String>,
+    },
+
+    /// Suggest JsonPath selectors for sample JSON body. If host set, print skeleton processor definition using them.
+    Suggest {
+        /// File path of sample JSON body.
+        body: PathBuf,
+
+        /// Value to look for, suggesting only selectors selecting it.
+        #[clap(long)]
+        find: Option<String>,
+
+        /// Hostname of skeleton processor.
+        #[clap(long)]
+        host: Option<String>,
+
+        /// Request method of skeleton processor rule.
+        #[clap(long, default_value = "GET")]
+        method: http::Method,
+
+        /// Request path of skeleton processor rule.
+        #[clap(long, default_value = "/")]
+        path: String,
+
+        /// Name of skeleton processor.
+        #[clap(long, default_value = "Suggested")]
+        name: String,
+    },
 }
 
 #[tokio::main]
 async fn main() {
     // Parse CLI args
     let config = Config::parse();
+    let keys = TrustedKeys::parse(&config.trusted_keys).expect("failed to parse trusted keys");
+    if let Some(salt) = config.redact_salt.clone() {
+        redact::set_salt(salt).expect("failed to set redaction salt");
+    }
+
+    // Run subcommand instead of server
+    if let Some(command) = &config.command {
+        let code = match command {
+            Command::Validate { path } => validate(path, &keys),
+            Command::Migrate { path, dry_run } => migrate(path, *dry_run),
+            Command::Test { path } => test(path),
+            Command::Replay { har, output } => replay(&config, &keys, har, output.as_deref()),
+            Command::Reprocess {
+                archive,
+                from,
+                to,
+                token,
+            } => {
+                let to = to.unwrap_or_else(Utc::now);
+                reprocess(&config, &keys, archive, *from, to, token.as_deref()).await
+            }
+            Command::Suggest {
+                body,
+                find,
+                host,
+                method,
+                path,
+                name,
+            } => suggest(body, find.as_deref(), host.as_deref(), method, path, name),
+        };
+        std::process::exit(code);
+    }
 
     init_logging();
 
@@ -72,50 +340,49 @@ async fn main() {
     init_metrics();
 
     // Load processor(s)
-    let processor_defs = match config.processor {
-        Some(path) => {
-            if !path.exists() {
-                panic!("processor path {path:?} is neither directory or file, may not exists")
-            }
-
-            if path.is_dir() {
-                log::debug!("looking for processor defs from directory {path:?}");
-                let entries = path
-                    .read_dir()
-                    .expect("failed to read directory")
-                    .into_iter()
-                    .map(|entry| entry.expect("failed to read entry").path());
-
-                entries
-                    .filter(|p| {
-                        if let Some(ext) = p.extension() {
-                            ext == "yaml" || ext == "yml"
-                        } else {
-                            false
-                        }
-                    })
-                    .collect()
-            } else {
-                log::debug!("specified processor path is single file: {path:?}");
+    let processors = Processors::new(
+        loader::load(config.processor.as_deref(), &keys).expect("failed to load processors"),
+    );
+
+    log::debug!("loaded processors: {processors:?}");
 
-                vec![path]
+    match (&config.server, &config.processor_endpoint) {
+        // Poll processors from server
+        (Some(server), Some(endpoint)) => {
+            let url = format!(
+                "{}/{}",
+                server.to_string().trim_end_matches('/'),
+                endpoint.trim_start_matches('/')
+            );
+            let mut fetcher = Fetcher::new(url, keys)
+                .interval(Duration::from_secs(config.processor_poll_interval));
+            if let Some(path) = config.processor_cache.clone() {
+                fetcher = fetcher.cache(path);
             }
-        }
-        None => {
-            // Load default processors
-            vec![
-                // include_str!("./processors/<NAME>.yaml")
-            ]
-        }
-    };
 
-    let processors = processor_defs
-        .into_iter()
-        .inspect(|p| log::debug!("loading processor def {p:?}"))
-        .map(|p| Processor::from_file(p).expect("failed to load file {path} as processor"))
-        .collect();
+            match fetcher.load_cache() {
+                Ok(Some(cached)) => processors
+                    .swap(cached)
+                    .expect("failed to load cached processor bundle"),
+                Ok(None) => log::debug!("no cached processor bundle"),
+                Err(e) => log::warn!("failed to load cached processor bundle: {e:#}"),
+            }
 
-    log::debug!("loaded processors: {processors:?}");
+            tokio::task::spawn(fetcher.run(processors.clone()));
+        }
+
+        // Reload processors on changes
+        _ => {
+            if let Some(path) = config.processor.clone() {
+                let reloader = Reloader::new(path, processors.clone(), keys);
+                tokio::task::spawn(async move {
+                    if let Err(e) = reloader.run().await {
+                        log::error!("processor reloader stopped: {e:#}");
+                    }
+                });
+            }
+        }
+    }
 
     // Run app
     let proxy_addr: SocketAddr = format!("{}:{}", config.host, config.port)
@@ -126,37 +393,426 @@ async fn main() {
         .parse()
         .expect("failed to parse socket address");
 
+    let mut web = Web::new().processors(processors.clone());
+    let mut collector = Collector::new(server_base(&config), processors.clone());
+
+    // Track changes of documents
+    let mut changes = ChangeStore::new(config.change_store_capacity);
+    if let Some(path) = config.change_store.clone() {
+        changes = changes.persist(path).expect("failed to load change store");
+
+        let changes = changes.clone();
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                if let Err(e) = changes.save() {
+                    log::warn!("failed to save change store: {e:#}");
+                }
+            }
+        });
+    }
+    collector = collector.changes(changes.clone());
+
+    // Redact documents with global policy
+    collector = collector.redact(redact_policy(&config).expect("failed to load redaction policy"));
+
+    // Route invalid documents to dead-letter sink
+    if let Some(path) = config.dead_letter.clone() {
+        collector =
+            collector.dead_letter(DeadLetter::open(path).expect("failed to open dead-letter file"));
+    }
+
+    // Suppress duplicate uploads
+    if config.dedup_ttl > 0 {
+        collector = collector.dedup(Dedup::new(
+            Duration::from_secs(config.dedup_ttl),
+            config.dedup_capacity,
+        ));
+    }
+
+    // Enable or disable processors and rules at runtime
+    let mut toggles = Toggles::new();
+    if let Some(path) = config.toggles.clone() {
+        toggles = toggles.persist(path).expect("failed to load toggles");
+    }
+    collector = collector.toggles(toggles.clone());
+    web = web.toggles(toggles);
+    if let Some(token) = config.admin_token.clone() {
+        web = web.token(token);
+    }
+
+    // Sample unmatched flows for endpoint discovery
+    if config.discovery {
+        let discovery = Discovery::new(config.discovery_rate, config.discovery_capacity);
+        collector = collector.discovery(discovery.clone());
+        web = web.discovery(discovery);
+    }
+
+    let mut handlers: Vec<Box<dyn Handler>> = vec![Box::new(collector)];
+
+    // Record flows for debugging
+    if let Some(dir) = config.record.clone() {
+        let mut recorder = Recorder::new(dir)
+            .users(config.record_user.clone())
+            .redact(config.record_redact.clone())
+            .rotate(config.record_max_entries, config.record_max_files);
+        if let Some(hosts) = config.record_host.clone() {
+            recorder = recorder.hosts(hosts);
+        }
+
+        handlers.push(Box::new(recorder));
+    }
+
+    // Archive raw flows for reprocessing
+    if let Some(dir) = config.archive.clone() {
+        let archive = Archive::new(
+            dir,
+            config.archive_max_size * 1024 * 1024,
+            Duration::from_secs(config.archive_max_age * 60 * 60),
+        );
+        handlers.push(Box::new(
+            Archiver::new(archive, processors).redact(config.archive_redact.clone()),
+        ));
+    }
+
     // TODO: Support CLI arguments for static proxy auth credentials
     let proxy = Proxy::new(
         "proxy",
         Client::default(),
-        vec![Box::new(Delegator::new(config.server.clone().map(|u| {
-            Uri::builder()
-                .scheme(u.scheme_str().unwrap())
-                .authority(u.authority().unwrap().to_string())
-                .path_and_query("")
-                .build()
-                .unwrap()
-        })))],
-        vec![Box::new(Collector::new(
-            config.server.clone().map(|u| {
-                Uri::builder()
-                    .scheme(u.scheme_str().unwrap())
-                    .authority(u.authority().unwrap().to_string())
-                    .path_and_query("")
-                    .build()
-                    .unwrap()
-            }),
-            processors,
-        ))],
+        vec![Box::new(Delegator::new(server_base(&config)))],
+        handlers,
     );
 
-    let web = Web::new();
-
     log::warn!("proxy listening on {}", proxy_addr);
     log::warn!("web listening on {}", web_addr);
-    if let Err(e) = tokio::try_join!(proxy.run(&proxy_addr), web.run(&web_addr)) {
-        log::error!("error occurred from server: {e}");
+    tokio::select! {
+        result = async { tokio::try_join!(proxy.run(&proxy_addr), web.run(&web_addr)) } => {
+            if let Err(e) = result {
+                log::error!("error occurred from server: {e}");
+            }
+        }
+        _ = tokio::signal::ctrl_c() => log::warn!("shutting down"),
+    }
+
+    // Save changes made since last tick
+    if let Err(e) = changes.save() {
+        log::warn!("failed to save change store: {e:#}");
+    }
+}
+
+/// Global redaction rules of `--redact-policy`, if set.
+fn redact_policy(config: &Config) -> anyhow::Result<Vec<redact::Rule>> {
+    match &config.redact_policy {
+        Some(path) => redact::load_policy(path),
+        None => Ok(vec![]),
+    }
+}
+
+/// Base URL of core server, without path.
+fn server_base(config: &Config) -> Option<Uri> {
+    config.server.clone().map(|u| {
+        Uri::builder()
+            .scheme(u.scheme_str().unwrap())
+            .authority(u.authority().unwrap().to_string())
+            .path_and_query("")
+            .build()
+            .unwrap()
+    })
+}
+
+/// Validate processor definitions and print problems found. Returns exit code.
+fn validate(path: &Path, keys: &TrustedKeys) -> i32 {
+    match validate::validate(path, keys) {
+        Ok(issues) if issues.is_empty() => {
+            println!("no problems found");
+            0
+        }
+        Ok(issues) => {
+            for issue in &issues {
+                println!("{issue}");
+            }
+            let errors = issues.iter().filter(|issue| !issue.warning).count();
+            eprintln!(
+                "{errors} problem(s), {warnings} warning(s) found",
+                warnings = issues.len() - errors
+            );
+            i32::from(errors > 0)
+        }
+        Err(e) => {
+            eprintln!("failed to validate: {e:#}");
+            2
+        }
+    }
+}
+
+/// Migrate processor definitions to current format version and print changes made. Returns exit code.
+fn migrate(path: &Path, dry_run: bool) -> i32 {
+    let defs = match loader::discover(path) {
+        Ok(defs) => defs,
+        Err(e) => {
+            eprintln!("failed to find processor defs: {e:#}");
+            return 2;
+        }
+    };
+
+    let mut failed = 0;
+    for def in defs {
+        if def
+            .extension()
+            .map_or(false, |ext| ext == loader::BUNDLE_EXTENSION)
+        {
+            println!("{def:?}: skipped bundle");
+            continue;
+        }
+
+        let migrated = std::fs::read_to_string(&def)
+            .map_err(Into::into)
+            .and_then(|source| version::migrate(&source));
+        let migration = match migrated {
+            Ok(version::Outcome::Migrated(migration)) => migration,
+            Ok(version::Outcome::UpToDate) => {
+                println!("{def:?}: up to date");
+                continue;
+            }
+            Ok(version::Outcome::Skipped(reason)) => {
+                println!("{def:?}: skipped, {reason}; migrate it by hand");
+                continue;
+            }
+            Err(e) => {
+                println!("{def:?}: error: {e:#}");
+                failed += 1;
+                continue;
+            }
+        };
+
+        for field in &migration.dropped {
+            println!("{def:?}: dropped unknown field `{field}`");
+        }
+        if !dry_run {
+            if let Err(e) = std::fs::write(&def, &migration.source) {
+                println!("{def:?}: error: {e}");
+                failed += 1;
+                continue;
+            }
+        }
+        println!("{def:?}: migrated to {}", version::API_VERSION);
+    }
+
+    i32::from(failed > 0)
+}
+
+/// Run test cases of processor definitions and print results. Returns exit code.
+fn test(path: &Path) -> i32 {
+    let defs = match loader::discover(path) {
+        Ok(defs) => defs,
+        Err(e) => {
+            eprintln!("failed to find processor defs: {e:#}");
+            return 2;
+        }
+    };
+
+    let definitions = match Definitions::read(path) {
+        Ok(definitions) => definitions,
+        Err(e) => {
+            eprintln!("failed to read processor defs: {e:#}");
+            return 2;
+        }
+    };
+
+    let (mut passed, mut failed) = (0, 0);
+    for def in defs {
+        if def
+            .extension()
+            .map_or(false, |ext| ext == loader::BUNDLE_EXTENSION)
+        {
+            println!("{def:?}: skipped bundle");
+            continue;
+        }
+
+        let base_dir = def.parent().unwrap_or_else(|| Path::new("."));
+        let results = match definitions.load(&def) {
+            Ok(Some(processor)) => testing::run(&processor, base_dir),
+            Ok(None) => {
+                println!("{def:?}: skipped rule library or abstract processor");
+                continue;
+            }
+            Err(e) => Err(e),
+        };
+        match results {
+            Ok(results) => {
+                for result in results {
+                    match &result.diff {
+                        None => {
+                            println!("{def:?}: {} / {} ... ok", result.processor, result.case);
+                            passed += 1;
+                        }
+                        Some(diff) => {
+                            println!("{def:?}: {} / {} ... FAILED", result.processor, result.case);
+                            println!("{diff}");
+                            failed += 1;
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                println!("{def:?}: error: {e:#}");
+                failed += 1;
+            }
+        }
+    }
+
+    println!("{passed} passed; {failed} failed");
+    match failed {
+        0 => 0,
+        _ => 1,
+    }
+}
+
+/// Replay HAR file with processors and print match counts. Returns exit code.
+fn replay(config: &Config, keys: &TrustedKeys, har: &Path, output: Option<&Path>) -> i32 {
+    let run = || -> anyhow::Result<_> {
+        let processors = loader::load(config.processor.as_deref(), keys)?;
+        let redact = redact_policy(config)?;
+        let har = Har::from_str(&std::fs::read_to_string(har)?)?;
+        let summary = match output {
+            Some(path) => replay::replay(
+                &har,
+                &processors,
+                &redact,
+                &mut BufWriter::new(File::create(path)?),
+            )?,
+            None => replay::replay(&har, &processors, &redact, &mut std::io::stdout().lock())?,
+        };
+
+        Ok(summary)
+    };
+
+    match run() {
+        Ok(summary) => {
+            for ((processor, rule), count) in &summary.matches {
+                eprintln!("{processor} / {rule}: {count} match(es)");
+            }
+            eprintln!(
+                "{entries} entries replayed, {errors} skipped, {documents} document(s) written",
+                entries = summary.entries,
+                errors = summary.errors,
+                documents = summary.documents
+            );
+            0
+        }
+        Err(e) => {
+            eprintln!("failed to replay: {e:#}");
+            2
+        }
+    }
+}
+
+/// Reprocess archived flows and print summary. Returns exit code.
+async fn reprocess(
+    config: &Config,
+    keys: &TrustedKeys,
+    archive: &Path,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    token: Option<&str>,
+) -> i32 {
+    let processors = match loader::load(config.processor.as_deref(), keys) {
+        Ok(processors) => Processors::new(processors),
+        Err(e) => {
+            eprintln!("failed to load processors: {e:#}");
+            return 2;
+        }
+    };
+
+    let redact = match redact_policy(config) {
+        Ok(redact) => redact,
+        Err(e) => {
+            eprintln!("failed to load redaction policy: {e:#}");
+            return 2;
+        }
+    };
+    let upload_as = match (server_base(config), token) {
+        (Some(u), Some(token)) => Some((u, Credentials::new("Bearer", token))),
+        (Some(_), None) => {
+            eprintln!("no token given, documents are not uploaded");
+            None
+        }
+        (None, _) => None,
+    };
+
+    match archive::reprocess(archive, from, to, processors, redact, upload_as).await {
+        Ok(summary) => {
+            eprintln!(
+                "{records} flow(s) reprocessed, {skipped} skipped, {uploaded} uploaded, {failed} failed",
+                records = summary.records,
+                skipped = summary.skipped,
+                uploaded = summary.uploaded,
+                failed = summary.failed
+            );
+            match summary.failed {
+                0 => 0,
+                _ => 1,
+            }
+        }
+        Err(e) => {
+            eprintln!("failed to reprocess: {e:#}");
+            2
+        }
+    }
+}
+
+/// Print selector suggestions for sample body, and skeleton processor if host given. Returns exit code.
+fn suggest(
+    body: &Path,
+    find: Option<&str>,
+    host: Option<&str>,
+    method: &http::Method,
+    path: &str,
+    name: &str,
+) -> i32 {
+    let run = || -> anyhow::Result<_> {
+        let body = serde_json::from_str(&std::fs::read_to_string(body)?)?;
+        let suggestions = suggest::suggest(&body, find)?;
+        let skeleton = match host {
+            Some(host) => Some(suggest::skeleton(name, host, method, path, &suggestions)?),
+            None => None,
+        };
+
+        Ok((suggestions, skeleton))
+    };
+
+    match run() {
+        Ok((suggestions, skeleton)) => {
+            for suggestion in &suggestions {
+                let mut examples: Vec<_> = suggestion
+                    .matches
+                    .iter()
+                    .take(3)
+                    .map(|v| v.to_string())
+                    .collect();
+                if suggestion.matches.len() > 3 {
+                    examples.push("...".to_string());
+                }
+                // Keep listing out of standard output if skeleton printed there
+                match skeleton {
+                    Some(_) => eprintln!("{}\t{}", suggestion.path, examples.join(", ")),
+                    None => println!("{}\t{}", suggestion.path, examples.join(", ")),
+                }
+            }
+            if let Some(skeleton) = skeleton {
+                print!("{skeleton}");
+            }
+            if suggestions.is_empty() {
+                eprintln!("no selectors found");
+                return 1;
+            }
+            0
+        }
+        Err(e) => {
+            eprintln!("failed to suggest selectors: {e:#}");
+            2
+        }
     }
 }
 
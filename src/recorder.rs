@@ -0,0 +1,254 @@
+//! Handler module recording proxied flows to HAR files, for debugging and capturing fixtures.
+
+use std::{fs,
+          path::PathBuf,
+          sync::{Arc, Mutex}};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use kkowa_proxy_lib::{http::{Request, Response},
+                      proxy::{Flow, Handler, Reverse}};
+use regex::Regex;
+use tracing::{debug, error};
+
+use crate::{auth::user_of,
+            collector::host::{absolutize, Target},
+            har::{Entry, Har, DEFAULT_REDACT}};
+
+/// Handler writing flows to rotating HAR files.
+#[derive(Debug)]
+pub struct Recorder {
+    /// Host matcher of flows to record. If not set, record all flows.
+    hosts: Option<Regex>,
+
+    /// Users to record flows of. If empty, record flows of all users.
+    users: Vec<String>,
+
+    /// Names of headers to redact.
+    redact: Vec<String>,
+
+    writer: Arc<Mutex<Writer>>,
+}
+
+impl Recorder {
+    /// Create new recorder writing HAR files to given directory.
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            hosts: None,
+            users: vec![],
+            redact: DEFAULT_REDACT.iter().map(|s| s.to_string()).collect(),
+            writer: Arc::new(Mutex::new(Writer::new(dir))),
+        }
+    }
+
+    /// Record only flows of hosts matching given regular expression.
+    pub fn hosts(mut self, hosts: Regex) -> Self {
+        self.hosts = Some(hosts);
+        self
+    }
+
+    /// Record only flows of given users.
+    pub fn users(mut self, users: Vec<String>) -> Self {
+        self.users = users;
+        self
+    }
+
+    /// Set names of headers to redact, replacing defaults.
+    pub fn redact(mut self, redact: Vec<String>) -> Self {
+        self.redact = redact;
+        self
+    }
+
+    /// Set maximum number of entries per file and number of files to keep.
+    pub fn rotate(self, max_entries: usize, max_files: usize) -> Self {
+        {
+            let mut writer = self.writer.lock().unwrap();
+            writer.max_entries = max_entries.max(1);
+            writer.max_files = max_files.max(1);
+        }
+        self
+    }
+
+    /// Check whether flow should be recorded.
+    fn matches(&self, flow: &Flow, resp: &Response) -> bool {
+        if !self.matches_host(&resp.request) {
+            return false;
+        }
+
+        if !self.users.is_empty() {
+            match flow.auth().and_then(user_of) {
+                Some(user) if self.users.contains(&user) => {}
+                _ => return false,
+            }
+        }
+
+        true
+    }
+
+    /// Check whether target host of request matches host filter, resolving it from `Host` header for origin-form
+    /// requests.
+    fn matches_host(&self, req: &Request) -> bool {
+        match &self.hosts {
+            Some(hosts) => Target::of(req).map_or(false, |target| hosts.is_match(&target.host)),
+            None => true,
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for Recorder {
+    async fn on_response(&self, flow: &Flow, mut resp: Response) -> Reverse {
+        absolutize(&mut resp.request, flow.tunnel());
+        if self.matches(flow, &resp) {
+            let mut entry = Entry::from_response(&resp, Utc::now());
+            entry.redact(&self.redact);
+
+            let writer = self.writer.clone();
+            tokio::task::spawn_blocking(move || {
+                if let Err(err) = writer.lock().unwrap().write(entry) {
+                    error!("failed to record flow: {err:#}");
+                }
+            });
+        }
+
+        Reverse::DoNothing
+    }
+}
+
+/// Writes entries to HAR file, rewriting whole file per entry as HAR is single JSON document.
+#[derive(Debug)]
+struct Writer {
+    dir: PathBuf,
+    max_entries: usize,
+    max_files: usize,
+
+    /// Current file and its contents.
+    current: Option<(PathBuf, Har)>,
+
+    /// Files written, oldest first.
+    files: Vec<PathBuf>,
+
+    /// Sequence number of next file.
+    seq: usize,
+}
+
+impl Writer {
+    fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            max_entries: 100,
+            max_files: 10,
+            current: None,
+            files: vec![],
+            seq: 0,
+        }
+    }
+
+    fn write(&mut self, entry: Entry) -> Result<()> {
+        let full = match &self.current {
+            Some((_, har)) => har.log.entries.len() >= self.max_entries,
+            None => true,
+        };
+        if full {
+            self.rotate()?;
+        }
+
+        let (path, har) = self.current.as_mut().unwrap();
+        har.log.entries.push(entry);
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(har)?)?;
+        fs::rename(&tmp, path)?;
+
+        Ok(())
+    }
+
+    /// Start new file, removing oldest ones exceeding limit.
+    fn rotate(&mut self) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        let name = format!(
+            "flows-{timestamp}-{seq}.har",
+            timestamp = Utc::now().format("%Y%m%dT%H%M%S"),
+            seq = self.seq
+        );
+        self.seq += 1;
+        let path = self.dir.join(name);
+        debug!("recording flows to {path:?}");
+
+        self.files.push(path.clone());
+        self.current = Some((path, Har::default()));
+
+        while self.files.len() > self.max_files {
+            let oldest = self.files.remove(0);
+            if let Err(err) = fs::remove_file(&oldest) {
+                error!("failed to remove old recording {oldest:?}: {err}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, str::FromStr};
+
+    use http::HeaderMap;
+    use kkowa_proxy_lib::http::{Headers, Method, Request, Uri, Version};
+    use regex::Regex;
+    use rstest::*;
+
+    use super::{Recorder, Writer};
+    use crate::har::Har;
+
+    fn entries() -> Vec<crate::har::Entry> {
+        Har::from_str(include_str!("collector/donuts.har"))
+            .unwrap()
+            .log
+            .entries
+    }
+
+    #[test]
+    fn writer_rotate() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer = Writer::new(dir.path().to_path_buf());
+        writer.max_entries = 1;
+        writer.max_files = 2;
+
+        for _ in 0..3 {
+            for entry in entries() {
+                writer.write(entry).unwrap();
+            }
+        }
+
+        let files: Vec<_> = std::fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(files.len(), 2);
+        assert_eq!(writer.files.len(), 2);
+
+        let har = Har::from_str(&std::fs::read_to_string(&writer.files[1]).unwrap()).unwrap();
+        assert_eq!(har.log.entries.len(), 1);
+    }
+
+    #[rstest]
+    #[case("http://shop.example.com/items", true)]
+    #[case("http://other.example.com/items", false)]
+    #[case("/items", true)] // Intercepted in tunnel
+    fn recorder_matches_host(#[case] uri: &'static str, #[case] expected: bool) {
+        let recorder =
+            Recorder::new(PathBuf::from("records")).hosts(Regex::new("^shop\\.").unwrap());
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "shop.example.com".parse().unwrap());
+        let req = Request::new(
+            Method::GET,
+            Uri::from_static(uri),
+            Version::HTTP_11,
+            Headers::from(headers),
+            vec![],
+        );
+
+        assert_eq!(recorder.matches_host(&req), expected);
+    }
+}
@@ -5,24 +5,71 @@
             Error, Method, StatusCode};
 use metrics_exporter_prometheus::PrometheusHandle;
 use once_cell::sync::OnceCell;
-use tracing::info;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::collector::{discovery::Discovery,
+                       drift::DRIFT,
+                       reload::Processors,
+                       toggle::{Toggle, Toggles}};
 
 pub(crate) static METRICS_HANDLE: OnceCell<PrometheusHandle> = OnceCell::new();
 
 /// HTTP server instance for internal purpose, such as serving health checks, metrics, etc.
 #[derive(Clone, Default)]
-pub struct Web {}
+pub struct Web {
+    /// Store of unmatched flow samples to serve. If not set, discovery endpoint is not available.
+    discovery: Option<Discovery>,
+
+    /// Loaded processors to list. If not set, processors endpoint is not available.
+    processors: Option<Processors>,
+
+    /// Runtime toggles of processors and rules to manage. If not set, toggle endpoints are not available.
+    toggles: Option<Toggles>,
+
+    /// Bearer token required by admin endpoints. If not set, those are not available.
+    token: Option<String>,
+}
 
 impl Web {
     pub fn new() -> Self {
-        Self {}
+        Self::default()
+    }
+
+    /// Serve samples of given discovery store.
+    pub fn discovery(mut self, discovery: Discovery) -> Self {
+        self.discovery = Some(discovery);
+        self
+    }
+
+    /// List given processors.
+    pub fn processors(mut self, processors: Processors) -> Self {
+        self.processors = Some(processors);
+        self
+    }
+
+    /// Manage given runtime toggles, reflecting those in processor listing.
+    pub fn toggles(mut self, toggles: Toggles) -> Self {
+        self.toggles = Some(toggles);
+        self
+    }
+
+    /// Require given bearer token for admin endpoints.
+    pub fn token(mut self, token: String) -> Self {
+        self.token = Some(token);
+        self
     }
 
     pub async fn run(&self, addr: &SocketAddr) -> Result<(), Error> {
-        let make_service = make_service_fn(move |_| async move {
-            let service = service_fn(serve);
+        let web = self.clone();
+        let make_service = make_service_fn(move |_| {
+            let web = web.clone();
+            async move {
+                let service = service_fn(move |req| serve(web.clone(), req));
 
-            Ok::<_, Infallible>(service)
+                Ok::<_, Infallible>(service)
+            }
         });
 
         hyper::Server::bind(addr)
@@ -41,6 +88,7 @@ async fn graceful_shutdown(&self) {
 }
 
 async fn serve(
+    web: Web,
     req: hyper::Request<hyper::Body>,
 ) -> Result<hyper::Response<hyper::Body>, hyper::Error> {
     let (version, method, uri) = (
@@ -60,6 +108,29 @@ async fn serve(
         // GET /metrics
         (Method::GET, "/metrics") => metrics(METRICS_HANDLE.get()).await,
 
+        // GET /selectors/drift
+        (Method::GET, "/selectors/drift") => drift().await,
+
+        // GET /discovery
+        (Method::GET, "/discovery") => discovery(web.discovery.as_ref()).await,
+
+        // GET /processors
+        (Method::GET, "/processors") => {
+            processors(web.processors.as_ref(), web.toggles.as_ref()).await
+        }
+
+        // GET /toggles
+        (Method::GET, "/toggles") => match authorize(&web, &req) {
+            Some(resp) => Ok(resp),
+            None => toggles(web.toggles.as_ref()).await,
+        },
+
+        // PUT /toggles
+        (Method::PUT, "/toggles") => match authorize(&web, &req) {
+            Some(resp) => Ok(resp),
+            None => set_toggle(&web, req).await,
+        },
+
         // Fallback
         (_, _) => not_found().await,
     }
@@ -83,6 +154,170 @@ async fn metrics(
     Ok(response)
 }
 
+async fn drift() -> Result<hyper::Response<hyper::Body>, hyper::Error> {
+    Ok(hyper::Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(serde_json::to_vec(&DRIFT.dropped()).unwrap().into())
+        .unwrap())
+}
+
+async fn discovery(
+    discovery: Option<&Discovery>,
+) -> Result<hyper::Response<hyper::Body>, hyper::Error> {
+    let response = match discovery {
+        Some(d) => hyper::Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(&d.samples()).unwrap().into())
+            .unwrap(),
+        None => hyper::Response::builder()
+            .status(StatusCode::NOT_IMPLEMENTED)
+            .body(hyper::body::Body::empty())
+            .unwrap(),
+    };
+
+    Ok(response)
+}
+
+async fn processors(
+    processors: Option<&Processors>,
+    toggles: Option<&Toggles>,
+) -> Result<hyper::Response<hyper::Body>, hyper::Error> {
+    let response = match processors {
+        Some(p) => {
+            let summaries: Vec<_> = p
+                .load()
+                .iter()
+                .map(|p| {
+                    let mut summary = p.summary();
+                    if let Some(t) = toggles {
+                        summary["enabled"] = json!(t.processor_enabled(p));
+                        for rule in summary["rules"].as_array_mut().into_iter().flatten() {
+                            let enabled = t.rule_enabled(p.name(), rule["name"].as_str().unwrap());
+                            rule["enabled"] = json!(enabled);
+                        }
+                    }
+
+                    summary
+                })
+                .collect();
+
+            hyper::Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(serde_json::to_vec(&summaries).unwrap().into())
+                .unwrap()
+        }
+        None => hyper::Response::builder()
+            .status(StatusCode::NOT_IMPLEMENTED)
+            .body(hyper::body::Body::empty())
+            .unwrap(),
+    };
+
+    Ok(response)
+}
+
+/// Check bearer token of admin request, returning error response if it is not authorized.
+fn authorize(web: &Web, req: &hyper::Request<hyper::Body>) -> Option<hyper::Response<hyper::Body>> {
+    let token = match (&web.token, &web.toggles) {
+        (Some(token), Some(_)) => token,
+        _ => {
+            return Some(
+                hyper::Response::builder()
+                    .status(StatusCode::NOT_IMPLEMENTED)
+                    .body(hyper::body::Body::empty())
+                    .unwrap(),
+            )
+        }
+    };
+
+    let given = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    // Compare digests so comparison time does not depend on matching prefix of token
+    match given {
+        Some(given) if Sha256::digest(given) == Sha256::digest(token) => None,
+        _ => {
+            warn!("unauthorized admin request to {uri}", uri = req.uri());
+
+            Some(
+                hyper::Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .header(header::WWW_AUTHENTICATE, "Bearer")
+                    .body("Unauthorized".into())
+                    .unwrap(),
+            )
+        }
+    }
+}
+
+async fn toggles(toggles: Option<&Toggles>) -> Result<hyper::Response<hyper::Body>, hyper::Error> {
+    let response = match toggles {
+        Some(t) => hyper::Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(&t.state()).unwrap().into())
+            .unwrap(),
+        None => hyper::Response::builder()
+            .status(StatusCode::NOT_IMPLEMENTED)
+            .body(hyper::body::Body::empty())
+            .unwrap(),
+    };
+
+    Ok(response)
+}
+
+async fn set_toggle(
+    web: &Web,
+    req: hyper::Request<hyper::Body>,
+) -> Result<hyper::Response<hyper::Body>, hyper::Error> {
+    let toggles = match &web.toggles {
+        Some(t) => t,
+        None => return self::toggles(None).await,
+    };
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    let toggle: Toggle = match serde_json::from_slice(&body) {
+        Ok(toggle) => toggle,
+        Err(err) => {
+            return Ok(hyper::Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(format!("Invalid toggle: {err}").into())
+                .unwrap())
+        }
+    };
+
+    // Reject unknown processors and rules, which would never take effect
+    if let Some(processors) = &web.processors {
+        let known = processors.load().iter().any(|p| {
+            p.name() == toggle.processor
+                && toggle
+                    .rule
+                    .as_ref()
+                    .map_or(true, |rule| p.rule_names().contains(rule))
+        });
+        if !known {
+            return Ok(hyper::Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body("Unknown processor or rule".into())
+                .unwrap());
+        }
+    }
+
+    if let Err(err) = toggles.set(&toggle) {
+        warn!("failed to save toggles: {err:#}");
+
+        return Ok(hyper::Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(format!("Failed to save toggles: {err}").into())
+            .unwrap());
+    }
+
+    self::toggles(Some(toggles)).await
+}
+
 async fn healthz() -> Result<hyper::Response<hyper::Body>, hyper::Error> {
     Ok(hyper::Response::builder()
         .status(StatusCode::OK)
@@ -103,6 +338,9 @@ mod tests {
     use anyhow::Result;
     use hyper::{body::to_bytes, StatusCode};
 
+    use super::Web;
+    use crate::collector::{discovery::Discovery, reload::Processors, toggle::Toggles, Processor};
+
     #[tokio::test]
     async fn healthz() -> Result<()> {
         let resp = super::healthz().await?;
@@ -129,6 +367,108 @@ async fn metrics() -> Result<()> {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn drift() -> Result<()> {
+        let resp = super::drift().await?;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        serde_json::from_slice::<serde_json::Value>(&to_bytes(resp.into_body()).await?)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn discovery() -> Result<()> {
+        let resp = super::discovery(Some(&Discovery::default())).await?;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(to_bytes(resp.into_body()).await?.to_vec(), b"[]");
+
+        let resp = super::discovery(None).await?;
+
+        assert_eq!(resp.status(), StatusCode::NOT_IMPLEMENTED);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn processors() -> Result<()> {
+        let processors = Processors::new(vec![
+            include_str!("../collector/donuts-processor.yaml").parse::<Processor>()?
+        ]);
+        let resp = super::processors(Some(&processors), None).await?;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(resp.into_body()).await?)?;
+        assert_eq!(body[0]["name"], "Name");
+        assert_eq!(body[0]["enabled"], true);
+        assert!(body[0]["rules"].is_array());
+
+        let resp = super::processors(None, None).await?;
+
+        assert_eq!(resp.status(), StatusCode::NOT_IMPLEMENTED);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn toggles() -> Result<()> {
+        let processors = Processors::new(vec![
+            include_str!("../collector/donuts-processor.yaml").parse::<Processor>()?
+        ]);
+        let toggles = Toggles::new();
+        let web = Web::new()
+            .processors(processors)
+            .toggles(toggles.clone())
+            .token("secret".to_string());
+        let put = |token: &str, body: &'static str| {
+            hyper::Request::builder()
+                .method("PUT")
+                .uri("/toggles")
+                .header("authorization", format!("Bearer {token}"))
+                .body(hyper::Body::from(body))
+                .unwrap()
+        };
+
+        let resp = super::serve(web.clone(), put("wrong", "{}")).await?;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = super::serve(
+            web.clone(),
+            put(
+                "secret",
+                r#"{"processor": "Name", "rule": "Unknown", "enabled": false}"#,
+            ),
+        )
+        .await?;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = super::serve(
+            web.clone(),
+            put(
+                "secret",
+                r#"{"processor": "Name", "rule": "Donuts", "enabled": false}"#,
+            ),
+        )
+        .await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(!toggles.rule_enabled("Name", "Donuts"));
+
+        let req = hyper::Request::builder()
+            .uri("/processors")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let resp = super::serve(web.clone(), req).await?;
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(resp.into_body()).await?)?;
+        assert_eq!(body[0]["rules"][0]["enabled"], false);
+
+        // Not available without token
+        let resp = super::serve(Web::new().toggles(toggles), put("", "{}")).await?;
+        assert_eq!(resp.status(), StatusCode::NOT_IMPLEMENTED);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn not_found() -> Result<()> {
         let resp = super::not_found().await?;
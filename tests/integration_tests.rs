@@ -5,3 +5,130 @@ fn help() {
     let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
     cmd.arg("--help").assert().success();
 }
+
+#[test]
+fn processor_poll_interval_zero() {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    let output = cmd
+        .args(["--processor-poll-interval", "0"])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--processor-poll-interval"));
+}
+
+#[test]
+fn validate() {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    cmd.args(["validate", "src/collector"]).assert().success();
+}
+
+#[test]
+fn validate_invalid() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("invalid.yaml");
+    std::fs::write(
+        &path,
+        include_str!("../src/collector/donuts-processor.yaml").replace("$[*].name", "$[*"),
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    let output = cmd.arg("validate").arg(&path).output().unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("invalid.yaml:22: rule Donuts: "));
+}
+
+#[test]
+fn test() {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    cmd.args(["test", "src/collector"]).assert().success();
+}
+
+#[test]
+fn replay() {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    let output = cmd
+        .args([
+            "--processor",
+            "src/collector/donuts-processor.yaml",
+            "replay",
+            "src/collector/donuts.har",
+        ])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 1);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Name / Donuts: 1 match(es)"));
+}
+
+#[test]
+fn replay_relative_processor() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("donuts.yaml"),
+        include_str!("../src/collector/donuts-processor.yaml"),
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    let output = cmd
+        .current_dir(dir.path())
+        .args(["--processor", "donuts.yaml", "replay"])
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/collector/donuts.har"
+        ))
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 1);
+}
+
+#[test]
+fn suggest() {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    let output = cmd
+        .args([
+            "suggest",
+            "src/collector/donuts.json",
+            "--find",
+            "Raised",
+            "--host",
+            "subdomain.domain.com",
+            "--path",
+            "/donuts",
+        ])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("$[*].name"));
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("hostname: ^subdomain\\.domain\\.com$")
+    );
+}
+
+#[test]
+fn migrate() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("donuts.yaml");
+    std::fs::write(
+        &path,
+        include_str!("../src/collector/donuts-processor.yaml"),
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    cmd.arg("migrate").arg(dir.path()).assert().success();
+
+    let migrated = std::fs::read_to_string(&path).unwrap();
+    assert!(migrated.contains("apiVersion: collector.kkowa.io/v1\nkind: Processor\n"));
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    cmd.arg("validate").arg(dir.path()).assert().success();
+}